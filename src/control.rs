@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::SocketAddr;
+
+/// 控制接口请求，每行一个JSON对象，如`{"command":"switch","screen":"laptop"}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// 列出已连接的客户端
+    Clients,
+    /// 当前激活的屏幕
+    Active,
+    /// 切换到指定屏幕，切换完成后回复
+    Switch { screen: String },
+    /// 断开客户端，客户端收到通知后释放按住的按键并停止，需要重新启动才能再次连接
+    Kick { name: String },
+    /// 锁定光标在当前屏幕
+    Lock,
+    /// 解锁光标
+    Unlock,
+    /// 重新加载配置文件
+    Reload,
+    /// Prometheus文本格式的运行指标
+    Metrics,
+}
+
+/// 控制接口响应，每行一个JSON对象，成功时`ok`为true并可能带有`data`，失败时带有`error`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ControlResponse {
+    /// 成功，不带数据
+    pub fn ok() -> Self {
+        ControlResponse {
+            ok: true,
+            data: None,
+            error: None,
+        }
+    }
+
+    /// 成功，带数据
+    pub fn data<T: Serialize>(data: T) -> Self {
+        match serde_json::to_value(data) {
+            Ok(data) => ControlResponse {
+                data: Some(data),
+                ..ControlResponse::ok()
+            },
+            Err(e) => ControlResponse::error(e),
+        }
+    }
+
+    pub fn error<E: ToString>(error: E) -> Self {
+        ControlResponse {
+            ok: false,
+            data: None,
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// 失败时转换为错误
+    pub fn into_result(self) -> Result<Option<Value>> {
+        match self.ok {
+            true => Ok(self.data),
+            false => Err(anyhow!(self.error.unwrap_or_default())),
+        }
+    }
+}
+
+/// `clients`请求返回的客户端
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientStatus {
+    pub name: String,
+    pub addr: SocketAddr,
+    /// 是否为当前激活的客户端
+    pub active: bool,
+}
+
+#[cfg(unix)]
+pub use unix::{send_request, ControlServer};
+
+#[cfg(unix)]
+mod unix {
+    use super::{ControlRequest, ControlResponse};
+    use anyhow::{anyhow, Result};
+    use log::{debug, error, info, warn};
+    use std::{
+        fs,
+        io::{BufRead, BufReader, ErrorKind, Write},
+        os::unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    use crate::net::server::POLL_INTERVAL;
+
+    /// 单个连接空闲多久后断开
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// 控制接口服务，监听Unix域套接字
+    pub struct ControlServer {
+        path: PathBuf,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl ControlServer {
+        /// 监听`path`，每个请求调用`handler`处理
+        ///
+        /// 套接字文件已存在时，没有其他实例在监听则删除后重新创建。
+        pub fn start<F>(path: &Path, handler: F) -> Result<Self>
+        where
+            F: Fn(ControlRequest) -> ControlResponse + Send + Sync + 'static,
+        {
+            if path.exists() {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(anyhow!("控制接口{}已被其他实例使用", path.display()));
+                }
+                fs::remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)
+                .map_err(|e| anyhow!("监听控制接口{}失败: {}", path.display(), e))?;
+            //只允许当前用户访问
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            listener.set_nonblocking(true)?;
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let handler = Arc::new(handler);
+            let thread = {
+                let stop = stop.clone();
+                thread::Builder::new()
+                    .name("minput-control".to_string())
+                    .spawn(move || {
+                        while !stop.load(Ordering::Relaxed) {
+                            match listener.accept() {
+                                Ok((stream, _)) => {
+                                    let handler = handler.clone();
+                                    thread::spawn(move || {
+                                        if let Err(e) = serve(stream, handler.as_ref()) {
+                                            debug!("control connection error: {}", e);
+                                        }
+                                    });
+                                }
+                                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                                    thread::sleep(POLL_INTERVAL)
+                                }
+                                Err(e) => warn!("接受控制连接错误: {}", e),
+                            }
+                        }
+                    })?
+            };
+            info!("control socket {}", path.display());
+            Ok(ControlServer {
+                path: path.to_path_buf(),
+                stop,
+                thread: Some(thread),
+            })
+        }
+
+        /// 停止监听并删除套接字文件
+        pub fn stop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                if thread.join().is_err() {
+                    error!("control thread panicked");
+                }
+                fs::remove_file(&self.path).ok();
+            }
+        }
+    }
+
+    impl Drop for ControlServer {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    /// 逐行处理一个连接的请求
+    fn serve<F>(stream: UnixStream, handler: &F) -> Result<()>
+    where
+        F: Fn(ControlRequest) -> ControlResponse,
+    {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<ControlRequest>(&line) {
+                Ok(request) => {
+                    debug!("control request: {:?}", request);
+                    handler(request)
+                }
+                Err(e) => ControlResponse::error(format!("无效的请求: {}", e)),
+            };
+            serde_json::to_writer(&mut writer, &response)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// 发送请求并等待响应
+    pub fn send_request(path: &Path, request: &ControlRequest) -> Result<ControlResponse> {
+        let mut stream = UnixStream::connect(path)
+            .map_err(|e| anyhow!("连接控制接口{}失败: {}", path.display(), e))?;
+        stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+        serde_json::to_writer(&mut stream, request)?;
+        stream.write_all(b"\n")?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// 非Unix系统不支持控制接口
+#[cfg(not(unix))]
+pub fn send_request(path: &std::path::Path, _request: &ControlRequest) -> Result<ControlResponse> {
+    Err(anyhow!("当前系统不支持控制接口{}", path.display()))
+}
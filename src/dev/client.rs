@@ -1,13 +1,545 @@
-use crate::CONFIG;
-use anyhow::Result;
-use log::info;
+use crate::{
+    dev::inject,
+    keyboard::{KeyTranslation, KeyboardLayout},
+    metrics::{Direction, Metrics, MetricsServer},
+    net::{
+        addr::resolve,
+        client::UdpClient,
+        discovery::{broadcast, discover, DISCOVERY_TIMEOUT},
+        protocol::{ClientInfo, Event, Flag, KeyMouse, Platform, Protocol, ServerInfo},
+        server::{HEARTBEAT_INTERVAL, POLL_INTERVAL},
+    },
+    status::{Notifier, Status},
+    Config, ConfigClient, Context,
+};
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+use rdev::simulate;
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-pub fn start() -> Result<()> {
-    let client_config = CONFIG.client.as_ref().expect("配置文件错误");
+/// 超过该时间未收到服务端报文时重新发送连接请求，服务端重启后不再认识原来的客户端
+const SERVER_TIMEOUT: Duration = Duration::from_secs(3);
+/// 服务端不可达时第一次重试的间隔
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+/// 服务端不可达时重试的最长间隔
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(8);
+
+/// 客户端实例，持有配置、屏幕和接收线程
+pub struct Client {
+    context: Arc<Context>,
+    /// 服务端回复的信息，连接成功前为None
+    server: Arc<RwLock<Option<ServerInfo>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+    notifier: Notifier,
+    metrics: Arc<Metrics>,
+    metrics_server: Option<MetricsServer>,
+}
+
+impl Client {
+    /// 检查配置并创建客户端，不连接服务器
+    pub fn new(config: Config) -> Result<Self> {
+        let context = Context::new(config)?;
+        context
+            .config
+            .client
+            .as_ref()
+            .ok_or_else(|| anyhow!("缺少client配置"))?;
+        info!("config info: {:#?}", context.config);
+        info!("display info: {:#?}", context.display);
+        info!("keyboard layout: {}", context.keyboard_layout.name);
+        Ok(Client {
+            context: Arc::new(context),
+            server: Arc::new(RwLock::new(None)),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+            notifier: Notifier::default(),
+            metrics: Arc::default(),
+            metrics_server: None,
+        })
+    }
+
+    fn config(&self) -> &ConfigClient {
+        //new中已检查
+        self.context.config.client.as_ref().expect("缺少client配置")
+    }
+
+    /// 连接服务器并启动接收线程，服务端回复前重复发送连接请求
+    pub fn start(&mut self) -> Result<()> {
+        if self.thread.is_some() {
+            return Err(anyhow!("服务已经启动"));
+        }
+        let config = self.config();
+        let server = server_addr(config, &[broadcast(config.discovery_port)])?;
+        info!("connect server [{}]", server);
+        let udp = UdpClient::connect(server)?;
+        udp.set_read_timeout(Some(POLL_INTERVAL))?;
+        let info = ClientInfo {
+            name: config.name.clone(),
+            display: self.context.display.clone(),
+            direction: config.direction,
+            keyboard_layout: self.context.keyboard_layout.name.clone(),
+        };
+        let init = Protocol::client_init(&info)?;
+        let addr = udp.local_addr()?;
+        if let Some(port) = self.context.config.metrics_port {
+            let metrics = MetricsServer::start(port, self.metrics.clone())?;
+            info!("metrics on http://{}/metrics", metrics.local_addr());
+            self.metrics_server = Some(metrics);
+        }
+
+        self.stop.store(false, Ordering::Relaxed);
+        let (context, server, stop, notifier, metrics) = (
+            self.context.clone(),
+            self.server.clone(),
+            self.stop.clone(),
+            self.notifier.clone(),
+            self.metrics.clone(),
+        );
+        self.thread = Some(thread::spawn(move || {
+            let mut held = HeldKeys::default();
+            let connection = Connection {
+                udp: &udp,
+                init,
+                metrics: &metrics,
+            };
+            let result = receive(&connection, &context, &server, &stop, &notifier, &mut held);
+            //主动停止时通知服务端，服务端不再向本机转发事件
+            if stop.load(Ordering::Relaxed) {
+                if let Err(e) = send(&udp, Protocol::disconnect(), &metrics) {
+                    debug!("send disconnect error: {}", e);
+                }
+            }
+            //退出前释放按住的按键，防止服务端停止后客户端按键卡住
+            for protocol in held.release_all() {
+                simulate_event(&protocol);
+            }
+            notifier.notify(Status::Stopped);
+            result
+        }));
+        info!("start client success");
+        self.notifier.notify(Status::Started { addr });
+        Ok(())
+    }
+
+    /// 设置状态回调
+    pub fn on_status<F>(&self, f: F)
+    where
+        F: Fn(&Status) + Send + Sync + 'static,
+    {
+        self.notifier.set(f);
+    }
+
+    /// 启动客户端，阻塞直到接收线程退出
+    pub fn run(&mut self) -> Result<()> {
+        self.start()?;
+        self.join()
+    }
+
+    /// 停止接收线程，通知服务端断开并释放按住的按键，返回接收线程的错误
+    pub fn stop(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(mut metrics) = self.metrics_server.take() {
+            metrics.stop();
+        }
+        self.join()
+    }
+
+    /// Prometheus文本格式的运行指标
+    pub fn metrics(&self) -> String {
+        self.metrics.render()
+    }
+
+    /// 已连接的服务端名称，服务端回复前为None
+    pub fn server_name(&self) -> Option<String> {
+        self.server
+            .read()
+            .ok()
+            .and_then(|server| server.as_ref().map(|s| s.name.clone()))
+    }
+
+    fn join(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| anyhow!("client thread panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            error!("client exit error: {}", e);
+        }
+    }
+}
+
+/// 服务器地址，未配置server_ip时向`targets`发送探测，连接第一个回复的服务器
+///
+/// server_ip为主机名时使用解析到的第一个地址。
+fn server_addr(config: &ConfigClient, targets: &[SocketAddr]) -> Result<SocketAddr> {
+    if let Some(ip) = &config.server_ip {
+        let port = config
+            .server_port
+            .ok_or_else(|| anyhow!("缺少server_port配置"))?;
+        return Ok(resolve(ip, port)?[0]);
+    }
+    info!("discover server on {:?}", targets);
+    let servers = discover(targets, DISCOVERY_TIMEOUT)?;
+    let server = servers
+        .into_iter()
+        .find(|server| match &config.server_fingerprint {
+            Some(fingerprint) => server.fingerprint == *fingerprint,
+            None => true,
+        })
+        .ok_or_else(|| anyhow!("未发现服务器，请配置server_ip"))?;
     info!(
-        "connect server [{}:{}]",
-        client_config.server_ip, client_config.server_port
+        "discovered server {} [{}] fingerprint {}",
+        server.name, server.addr, server.fingerprint
     );
+    Ok(server.addr)
+}
+
+/// 按住的按键和鼠标按钮，客户端停止时释放
+#[derive(Debug, Default)]
+struct HeldKeys {
+    held: Vec<Protocol>,
+}
+
+impl HeldKeys {
+    /// 按模拟的事件更新按住的按键
+    fn update(&mut self, protocol: &Protocol) {
+        if protocol.flag != Flag::KeyMouse {
+            return;
+        }
+        let same = |p: &Protocol| p.key_mouse == protocol.key_mouse && p.raw == protocol.raw;
+        match protocol.event {
+            Event::Press if !self.held.iter().any(same) => self.held.push(protocol.clone()),
+            Event::Release => self.held.retain(|p| !same(p)),
+            _ => {}
+        }
+    }
 
+    /// 释放所有按住的按键，后按下的先释放
+    fn release_all(&mut self) -> Vec<Protocol> {
+        self.held
+            .drain(..)
+            .rev()
+            .map(|mut protocol| {
+                protocol.event = Event::Release;
+                protocol
+            })
+            .collect()
+    }
+}
+
+/// 与服务端的连接
+struct Connection<'a> {
+    udp: &'a UdpClient,
+    /// 连接请求，服务端回复前重复发送
+    init: Protocol,
+    metrics: &'a Metrics,
+}
+
+/// 接收并模拟服务端发送的事件，直到`stop`置位
+///
+/// 服务端未启动、重启时报文被拒绝（ConnectionRefused），等待后重新发送连接请求，等待时间逐次加倍。
+fn receive(
+    connection: &Connection,
+    context: &Context,
+    server: &RwLock<Option<ServerInfo>>,
+    stop: &AtomicBool,
+    notifier: &Notifier,
+    held: &mut HeldKeys,
+) -> Result<()> {
+    let Connection { udp, metrics, .. } = *connection;
+    //服务端回复前不转换按键
+    let mut translation = KeyTranslation::default();
+    let mut heartbeat = Instant::now();
+    let mut connected = false;
+    let mut init_sent: Option<Instant> = None;
+    let mut received = Instant::now();
+    let mut retry = RETRY_INTERVAL;
+    while !stop.load(Ordering::Relaxed) {
+        if connected && received.elapsed() >= SERVER_TIMEOUT {
+            warn!("no response from server in {:?}, reconnect", SERVER_TIMEOUT);
+            connected = false;
+        }
+        if !connected {
+            if init_sent.is_none_or(|at| at.elapsed() >= HEARTBEAT_INTERVAL) {
+                init_sent = Some(Instant::now());
+                if let Err(e) = send(udp, connection.init.clone(), metrics) {
+                    debug!("send client init error: {}", e);
+                }
+            }
+        } else if heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+            heartbeat = Instant::now();
+            if let Err(e) = send(udp, Protocol::heartbeat(metrics.timestamp()), metrics) {
+                debug!("send heartbeat error: {}", e);
+            }
+        }
+        let mut protocol = match udp.recv() {
+            Ok(Some(protocol)) => protocol,
+            Ok(None) => continue,
+            Err(e) if is_refused(&e) => {
+                warn!("server unreachable, retry in {:?}", retry);
+                connected = false;
+                init_sent = None;
+                sleep(retry, stop);
+                retry = (retry * 2).min(MAX_RETRY_INTERVAL);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        received = Instant::now();
+        retry = RETRY_INTERVAL;
+        debug!("recv {:?}", protocol);
+        metrics.count(Direction::Received, &protocol);
+        match protocol.flag {
+            Flag::ServerInitConnection => match protocol.server_info() {
+                Ok(info) => {
+                    connected = true;
+                    info!(
+                        "connected to server {}, keyboard layout: {}",
+                        info.name, info.keyboard_layout
+                    );
+                    translation = match KeyboardLayout::load(&info.keyboard_layout) {
+                        Ok(layout) => KeyTranslation::new(&layout, &context.keyboard_layout),
+                        Err(e) => {
+                            warn!("load server keyboard layout error: {}", e);
+                            KeyTranslation::default()
+                        }
+                    };
+                    let name = info.name.clone();
+                    if let Ok(mut server) = server.write() {
+                        *server = Some(info);
+                    }
+                    notifier.notify(Status::Connected { server: name });
+                }
+                Err(e) => warn!("invalid server info: {}", e),
+            },
+            Flag::KeyMouse => {
+                protocol.key_mouse = translation.translate(protocol.key_mouse);
+                held.update(&protocol);
+                let start = Instant::now();
+                if simulate_event(&protocol) {
+                    metrics.observe_inject(start.elapsed());
+                } else {
+                    metrics.count(Direction::Dropped, &protocol);
+                }
+            }
+            Flag::Text => match protocol.to_text() {
+                Ok(text) => {
+                    let start = Instant::now();
+                    match inject::text(&text) {
+                        Ok(_) => metrics.observe_inject(start.elapsed()),
+                        Err(e) => {
+                            warn!("simulate text error: {}", e);
+                            metrics.count(Direction::Dropped, &protocol);
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("invalid text: {}", e);
+                    metrics.count(Direction::Dropped, &protocol);
+                }
+            },
+            Flag::Heartbeat => {
+                if let Err(e) = send(udp, protocol.heartbeat_reply(), metrics) {
+                    debug!("reply heartbeat error: {}", e);
+                }
+            }
+            Flag::Disconnect => {
+                info!("disconnected by server");
+                break;
+            }
+            Flag::HeartbeatReply => match protocol.heartbeat_timestamp() {
+                Some(timestamp) => metrics.observe_rtt(timestamp),
+                None => warn!("invalid heartbeat reply"),
+            },
+            _ => {
+                warn!("unknown protocol: {:?}", protocol);
+                metrics.count(Direction::Dropped, &protocol);
+            }
+        }
+    }
     Ok(())
 }
+
+/// 服务端端口未打开，部分平台上返回ConnectionReset
+fn is_refused(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+        )
+    })
+}
+
+/// 等待`duration`，`stop`置位时提前返回
+fn sleep(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL.min(deadline - Instant::now()));
+    }
+}
+
+/// 发送报文并计数
+fn send(udp: &UdpClient, protocol: Protocol, metrics: &Metrics) -> Result<()> {
+    let result = udp.send(protocol.clone());
+    let direction = match result {
+        Ok(_) => Direction::Sent,
+        Err(_) => Direction::Dropped,
+    };
+    metrics.count(direction, &protocol);
+    result
+}
+
+/// 模拟服务端发送的鼠标键盘事件，返回是否模拟成功
+fn simulate_event(protocol: &Protocol) -> bool {
+    match protocol.event {
+        Event::RelativeMove(dx, dy) => {
+            if let Err(e) = inject::move_relative(dx, dy) {
+                warn!("simulate relative move error: {}", e);
+                return false;
+            }
+            return true;
+        }
+        Event::Wheel(delta) => {
+            let horizontal = protocol.key_mouse == KeyMouse::MouseHorizontalWheel;
+            if let Err(e) = inject::scroll(horizontal, delta) {
+                warn!("simulate wheel error: {}", e);
+                return false;
+            }
+            return true;
+        }
+        Event::Press | Event::Release => {
+            //附加按钮rdev不能在所有平台上模拟
+            if let Some(n) = protocol.key_mouse.extra_button() {
+                match Platform::current().extra_button_code(n) {
+                    Some(code) => {
+                        let press = protocol.event == Event::Press;
+                        if let Err(e) = inject::button(code, press) {
+                            warn!("simulate extra button {} error: {}", n, e);
+                            return false;
+                        }
+                        return true;
+                    }
+                    None => {
+                        warn!("extra button {} is not supported on this platform", n);
+                        return false;
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    match protocol.to_event_type() {
+        Some(event_type) => match simulate(&event_type) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("simulate {:?} error: {:?}", event_type, e);
+                false
+            }
+        },
+        None => {
+            if let Some(raw) = protocol.raw {
+                warn!("raw code {:?} is not supported on this platform", raw);
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{server_addr, HeldKeys};
+    use crate::{
+        net::{
+            discovery::DiscoveryResponder,
+            free_port,
+            protocol::{Announce, Event, Flag, KeyMouse, Protocol},
+        },
+        ConfigClient,
+    };
+
+    #[test]
+    fn test_discover_server() {
+        let mut responders = vec![];
+        let mut targets = vec![];
+        for (name, fingerprint) in [("a", "aaaa"), ("b", "bbbb")] {
+            let announce = Announce {
+                name: name.to_string(),
+                port: 48899,
+                fingerprint: fingerprint.to_string(),
+            };
+            let port = free_port();
+            let responder =
+                DiscoveryResponder::start(&["127.0.0.1:48899".parse().unwrap()], port, announce)
+                    .unwrap();
+            targets.push(format!("127.0.0.1:{}", port).parse().unwrap());
+            responders.push(responder);
+        }
+        let mut config: ConfigClient = serde_yaml::from_str("name: c\ndirection: left\n").unwrap();
+        assert!(server_addr(&config, &targets).is_ok());
+
+        config.server_fingerprint = Some("bbbb".to_string());
+        let addr = server_addr(&config, &targets).unwrap();
+        assert_eq!(addr, "127.0.0.1:48899".parse().unwrap());
+
+        config.server_fingerprint = Some("cccc".to_string());
+        assert!(server_addr(&config, &targets).is_err());
+
+        //配置了地址时不发现
+        config.server_ip = Some("::1".to_string());
+        config.server_port = Some(48900);
+        let addr = server_addr(&config, &[]).unwrap();
+        assert_eq!(addr, "[::1]:48900".parse().unwrap());
+    }
+
+    #[test]
+    fn test_held_keys() {
+        let mut held = HeldKeys::default();
+        held.update(&Protocol::new(
+            Flag::KeyMouse,
+            KeyMouse::ShiftLeft,
+            Event::Press,
+        ));
+        held.update(&Protocol::new(Flag::KeyMouse, KeyMouse::KeyA, Event::Press));
+        held.update(&Protocol::new(Flag::KeyMouse, KeyMouse::KeyA, Event::Press));
+        held.update(&Protocol::new(
+            Flag::KeyMouse,
+            KeyMouse::MouseLeft,
+            Event::Press,
+        ));
+        held.update(&Protocol::new(
+            Flag::KeyMouse,
+            KeyMouse::KeyA,
+            Event::Release,
+        ));
+        let released: Vec<_> = held
+            .release_all()
+            .into_iter()
+            .map(|p| (p.key_mouse, p.event))
+            .collect();
+        assert_eq!(
+            released,
+            vec![
+                (KeyMouse::MouseLeft, Event::Release),
+                (KeyMouse::ShiftLeft, Event::Release)
+            ]
+        );
+        assert!(held.release_all().is_empty());
+    }
+}
@@ -0,0 +1,650 @@
+use crate::{
+    dev::{
+        guard::EdgeGuard,
+        mapping::{edge_ratio, entry_point, map_delta, scale_at},
+        toggle,
+    },
+    layout::{Layout, Neighbor},
+    net::{protocol::KeyMouse, server::Client},
+    ConfigClientDirection, ConfigEdgeGuard, Display,
+};
+use log::info;
+use std::time::Instant;
+
+/// 屏幕边缘宽度，光标进入该范围即视为到达屏幕边缘
+pub const EDGE_WIDTH: f64 = 10.0;
+
+/// 鼠标移动结果
+#[derive(Debug, PartialEq)]
+pub enum Motion {
+    /// 光标在服务器屏幕上移动
+    Local,
+    /// 光标在客户端屏幕上移动，值为客户端屏幕坐标及换算到客户端屏幕的移动偏移量
+    Remote { x: f64, y: f64, dx: f64, dy: f64 },
+    /// 光标切换到另一个屏幕，值为新屏幕名字及进入坐标
+    Switch { screen: String, x: f64, y: f64 },
+}
+
+/// 屏幕边缘切换
+///
+/// 光标离开服务器屏幕后，服务器光标的移动偏移量叠加到当前屏幕的虚拟光标上，
+/// 虚拟光标到达边缘时按布局切换到相邻屏幕，因此可以从客户端直接切换到另一个客户端。
+pub struct EdgeSwitcher {
+    layout: Layout,
+    /// 服务器屏幕名字
+    server: String,
+    /// 服务器屏幕
+    display: Display,
+    /// 当前激活的屏幕名字
+    screen: String,
+    /// 当前屏幕上的光标位置
+    position: (f64, f64),
+    /// 服务器光标上一次的位置，用于计算移动偏移量
+    last: Option<(f64, f64)>,
+    /// 等待复位的服务器光标位置
+    warp: Option<(f64, f64)>,
+    /// 光标锁定在当前屏幕，不切换屏幕
+    locked: bool,
+    /// 边缘切换条件
+    guard: EdgeGuard,
+}
+
+impl EdgeSwitcher {
+    pub fn new(layout: Layout, server: &str, display: Display) -> Self {
+        EdgeSwitcher {
+            layout,
+            server: server.to_string(),
+            display,
+            screen: server.to_string(),
+            position: (0.0, 0.0),
+            last: None,
+            warp: None,
+            locked: false,
+            guard: EdgeGuard::default(),
+        }
+    }
+
+    /// 设置边缘切换条件
+    pub fn set_guard(&mut self, config: ConfigEdgeGuard) {
+        self.guard = EdgeGuard::new(config);
+    }
+
+    /// 替换屏幕布局，保持当前屏幕和光标位置
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// 更新鼠标按钮按住状态，按住时按配置决定是否允许切换
+    pub fn set_button_held(&mut self, held: bool) {
+        self.guard.set_button_held(held);
+    }
+
+    /// 当前激活的屏幕名字
+    pub fn screen(&self) -> &str {
+        &self.screen
+    }
+
+    /// 当前是否在服务器屏幕上
+    pub fn is_local(&self) -> bool {
+        self.screen == self.server
+    }
+
+    /// 处理服务器光标移动
+    pub fn on_move(&mut self, x: f64, y: f64, clients: &[Client]) -> Motion {
+        self.on_move_at(x, y, clients, Instant::now())
+    }
+
+    /// 处理`now`时刻的服务器光标移动
+    pub fn on_move_at(&mut self, x: f64, y: f64, clients: &[Client], now: Instant) -> Motion {
+        let (last_x, last_y) = self.last.unwrap_or((x, y));
+        self.last = Some((x, y));
+        let mut delta = (0.0, 0.0);
+
+        let display = if self.is_local() {
+            self.position = (x, y);
+            self.display.clone()
+        } else {
+            match self.display_of(&self.screen, clients) {
+                Some(display) => {
+                    delta = map_delta(
+                        (x - last_x, y - last_y),
+                        scale_at(&self.display, last_x, last_y),
+                        scale_at(&display, self.position.0, self.position.1),
+                    );
+                    self.position =
+                        display.clamp(self.position.0 + delta.0, self.position.1 + delta.1);
+                    //服务器光标接近边缘时复位到屏幕中心，保证可以继续计算偏移量
+                    if edge(x, y, &self.display).is_some() {
+                        self.warp = Some(self.display.center());
+                    }
+                    display
+                }
+                None => {
+                    //客户端已断开，回到服务器屏幕
+                    let (x, y) = self.display.center();
+                    return self.switch(self.server.clone(), x, y);
+                }
+            }
+        };
+
+        if let Some(motion) = self.edge_switch(&display, clients, now) {
+            return motion;
+        }
+
+        if self.is_local() {
+            Motion::Local
+        } else {
+            Motion::Remote {
+                x: self.position.0,
+                y: self.position.1,
+                dx: delta.0,
+                dy: delta.1,
+            }
+        }
+    }
+
+    /// 定时检查：当前客户端已断开时回到服务器屏幕；光标停留在边缘时按`now`重新检查切换条件，
+    /// 光标不再移动时停留时间到达后也能切换
+    pub fn tick(&mut self, clients: &[Client], now: Instant) -> Option<Motion> {
+        let display = if self.is_local() {
+            self.display.clone()
+        } else {
+            match self.display_of(&self.screen, clients) {
+                Some(display) => display,
+                None => return Some(self.home()),
+            }
+        };
+        if !self.guard.is_waiting() {
+            return None;
+        }
+        self.edge_switch(&display, clients, now)
+    }
+
+    /// 光标在`display`边缘且满足切换条件时切换到相邻屏幕
+    fn edge_switch(
+        &mut self,
+        display: &Display,
+        clients: &[Client],
+        now: Instant,
+    ) -> Option<Motion> {
+        let direction = edge(self.position.0, self.position.1, display).filter(|_| !self.locked);
+        let allowed = self.guard.allow(direction, self.position, display, now);
+        let direction = direction.filter(|_| allowed)?;
+        let (neighbor, to) = self.neighbor(direction, clients)?;
+        let (x, y) = entry_point(direction, self.position, display, &to, neighbor.offset);
+        //只允许在配置的边缘范围内切换
+        let ratio = if neighbor.range.target {
+            edge_ratio(direction, (x, y), &to)
+        } else {
+            edge_ratio(direction, self.position, display)
+        };
+        neighbor
+            .range
+            .contains(ratio)
+            .then(|| self.switch(neighbor.screen, x, y))
+    }
+
+    /// 直接切换到指定屏幕，光标放在屏幕中心，屏幕未连接时返回None
+    pub fn jump(&mut self, screen: &str, clients: &[Client]) -> Option<Motion> {
+        let (x, y) = self.display_of(screen, clients)?.center();
+        Some(self.switch(screen.to_string(), x, y))
+    }
+
+    /// 回到服务器屏幕
+    pub fn home(&mut self) -> Motion {
+        let (x, y) = self.display.center();
+        self.switch(self.server.clone(), x, y)
+    }
+
+    /// 按服务器、客户端连接顺序切换到下一个屏幕
+    pub fn cycle(&mut self, clients: &[Client]) -> Motion {
+        let screens: Vec<&str> = std::iter::once(self.server.as_str())
+            .chain(clients.iter().map(|c| c.info.name.as_str()))
+            .collect();
+        let next = screens
+            .iter()
+            .position(|s| *s == self.screen)
+            .map(|i| (i + 1) % screens.len())
+            .unwrap_or_default();
+        let screen = screens[next].to_string();
+        self.jump(&screen, clients).unwrap_or_else(|| self.home())
+    }
+
+    /// 光标是否锁定在当前屏幕
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// 锁定或解锁光标，锁定时到达边缘不切换屏幕
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    /// 将服务器光标复位到屏幕中心，相对移动模式下每次移动后调用
+    ///
+    /// 光标已在中心时不复位，复位本身产生的移动事件不会再次触发复位。
+    pub fn recenter(&mut self) {
+        let center = self.display.center();
+        if self.last != Some(center) {
+            self.warp = Some(center);
+        }
+    }
+
+    /// 获取需要复位的服务器光标位置，调用方负责移动服务器光标
+    pub fn take_warp(&mut self) -> Option<(f64, f64)> {
+        let warp = self.warp.take();
+        if warp.is_some() {
+            self.last = warp;
+        }
+        warp
+    }
+
+    fn switch(&mut self, screen: String, x: f64, y: f64) -> Motion {
+        self.guard.reset();
+        self.screen = screen.clone();
+        self.position = (x, y);
+        self.warp = Some(if self.is_local() {
+            (x, y)
+        } else {
+            self.display.center()
+        });
+        Motion::Switch { screen, x, y }
+    }
+
+    /// 当前屏幕指定方向上相邻且已连接的屏幕
+    fn neighbor(
+        &self,
+        direction: ConfigClientDirection,
+        clients: &[Client],
+    ) -> Option<(Neighbor, Display)> {
+        let neighbor = match self.layout.neighbor(&self.screen, direction) {
+            Some(neighbor) => neighbor.clone(),
+            //布局中未配置的客户端，按客户端配置的方向放置在服务器屏幕旁
+            None if self.is_local() => Neighbor::new(
+                clients
+                    .iter()
+                    .find(|c| !self.layout.contains(&c.info.name) && c.info.direction == direction)?
+                    .info
+                    .name
+                    .clone(),
+            ),
+            None => {
+                let client = clients.iter().find(|c| c.info.name == self.screen)?;
+                if self.layout.contains(&client.info.name)
+                    || client.info.direction.opposite() != direction
+                {
+                    return None;
+                }
+                Neighbor::new(self.server.clone())
+            }
+        };
+        let display = self.display_of(&neighbor.screen, clients)?;
+        Some((neighbor, display))
+    }
+
+    fn display_of(&self, screen: &str, clients: &[Client]) -> Option<Display> {
+        if screen == self.server {
+            return Some(self.display.clone());
+        }
+        clients
+            .iter()
+            .find(|c| c.info.name == screen)
+            .map(|c| c.info.display.clone())
+    }
+}
+
+/// 锁定键状态，读取系统记录的切换状态（指示灯）
+///
+/// 无法读取时（如macOS上的ScrollLock）按一次打开、再按一次关闭，启动时视为关闭。
+pub struct LockKey {
+    key: Option<KeyMouse>,
+    on: bool,
+    /// 读取按键的切换状态
+    query: fn(KeyMouse) -> Option<bool>,
+}
+
+impl LockKey {
+    pub fn new(key: Option<KeyMouse>) -> Self {
+        Self::with_query(key, toggle::toggled)
+    }
+
+    fn with_query(key: Option<KeyMouse>, query: fn(KeyMouse) -> Option<bool>) -> Self {
+        let on = key.and_then(query).unwrap_or_default();
+        if let Some(key) = key {
+            info!("lock key {:?} on: {}", key, on);
+        }
+        LockKey { key, on, query }
+    }
+
+    /// 处理按键事件，返回是否为锁定键，锁定键只在服务器上生效，不发送给客户端
+    pub fn on_key(&mut self, key: KeyMouse, press: bool) -> bool {
+        if self.key != Some(key) {
+            return false;
+        }
+        //按下时系统可能还未切换状态，释放时读取
+        if !press {
+            self.on = (self.query)(key).unwrap_or(!self.on);
+            info!("lock key {:?} on: {}", key, self.on);
+        }
+        true
+    }
+
+    /// 锁定键是否打开
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+}
+
+/// 光标所在的屏幕边缘，向该方向移动`EDGE_WIDTH`后离开所有显示器即视为到达边缘
+fn edge(x: f64, y: f64, display: &Display) -> Option<ConfigClientDirection> {
+    [
+        ConfigClientDirection::Left,
+        ConfigClientDirection::Right,
+        ConfigClientDirection::Up,
+        ConfigClientDirection::Down,
+    ]
+    .into_iter()
+    .find(|direction| {
+        let (dx, dy) = match direction {
+            ConfigClientDirection::Left => (-EDGE_WIDTH, 0.0),
+            ConfigClientDirection::Right => (EDGE_WIDTH, 0.0),
+            ConfigClientDirection::Up => (0.0, -EDGE_WIDTH),
+            ConfigClientDirection::Down => (0.0, EDGE_WIDTH),
+        };
+        !display.contains(x + dx, y + dy)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EdgeSwitcher, LockKey, Motion};
+    use crate::{
+        layout::Layout,
+        net::{
+            protocol::{ClientInfo, KeyMouse},
+            server::Client,
+        },
+        ConfigClientDirection, ConfigEdgeGuard, ConfigLayoutLink, Display, Monitor,
+    };
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::{Duration, Instant},
+    };
+
+    fn client(name: &str, port: u16) -> Client {
+        Client {
+            addr: ([127, 0, 0, 1], port).into(),
+            info: ClientInfo {
+                name: name.to_string(),
+                display: Display::new(1000, 800),
+                direction: ConfigClientDirection::Right,
+                keyboard_layout: "us".to_string(),
+            },
+            last_seen: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_chained_switch() {
+        let layout = Layout::new(&[
+            ConfigLayoutLink {
+                from: "server".to_string(),
+                direction: ConfigClientDirection::Right,
+                to: "a".to_string(),
+                offset: 0,
+                start: 0.0,
+                end: 100.0,
+            },
+            ConfigLayoutLink {
+                from: "a".to_string(),
+                direction: ConfigClientDirection::Down,
+                to: "b".to_string(),
+                offset: 0,
+                start: 0.0,
+                end: 100.0,
+            },
+        ]);
+        let clients = vec![client("a", 1), client("b", 2)];
+        let mut switcher = EdgeSwitcher::new(layout, "server", Display::new(1920, 1080));
+
+        assert_eq!(switcher.on_move(500.0, 500.0, &clients), Motion::Local);
+        assert_eq!(
+            switcher.on_move(1915.0, 540.0, &clients),
+            Motion::Switch {
+                screen: "a".to_string(),
+                x: 10.0,
+                y: 400.0
+            }
+        );
+        assert_eq!(switcher.take_warp(), Some((960.0, 540.0)));
+
+        //在客户端a上向下移动到边缘，直接切换到客户端b
+        assert_eq!(
+            switcher.on_move(960.0, 700.0, &clients),
+            Motion::Remote {
+                x: 10.0,
+                y: 560.0,
+                dx: 0.0,
+                dy: 160.0
+            }
+        );
+        assert_eq!(
+            switcher.on_move(960.0, 940.0, &clients),
+            Motion::Switch {
+                screen: "b".to_string(),
+                x: 10.0,
+                y: 10.0
+            }
+        );
+        assert!(!switcher.is_local());
+    }
+
+    #[test]
+    fn test_direction_fallback() {
+        let clients = vec![client("a", 1)];
+        let mut switcher = EdgeSwitcher::new(Layout::default(), "server", Display::new(1920, 1080));
+        assert_eq!(
+            switcher.on_move(1919.0, 540.0, &clients),
+            Motion::Switch {
+                screen: "a".to_string(),
+                x: 10.0,
+                y: 400.0
+            }
+        );
+        switcher.take_warp();
+        assert_eq!(
+            switcher.on_move(940.0, 540.0, &clients),
+            Motion::Switch {
+                screen: "server".to_string(),
+                x: 1909.0,
+                y: 540.0
+            }
+        );
+        assert!(switcher.is_local());
+    }
+
+    #[test]
+    fn test_multi_monitor_edge() {
+        //服务器主显示器右侧有一个位置偏下的显示器
+        let display = Display {
+            monitors: vec![
+                Monitor::new(0, 0, 1920, 1080),
+                Monitor::new(1920, 520, 1920, 1080),
+            ],
+        };
+        let clients = vec![client("a", 1)];
+        let mut switcher = EdgeSwitcher::new(Layout::default(), "server", display);
+        //两个显示器相接处不是边缘
+        assert_eq!(switcher.on_move(1915.0, 600.0, &clients), Motion::Local);
+        //主显示器右上方没有显示器，同样是右侧边缘
+        assert_eq!(
+            switcher.on_move(1915.0, 100.0, &clients),
+            Motion::Switch {
+                screen: "a".to_string(),
+                x: 10.0,
+                y: 50.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_jump_and_lock() {
+        let clients = vec![client("a", 1), client("b", 2)];
+        let mut switcher = EdgeSwitcher::new(Layout::default(), "server", Display::new(1920, 1080));
+        assert_eq!(
+            switcher.jump("b", &clients),
+            Some(Motion::Switch {
+                screen: "b".to_string(),
+                x: 500.0,
+                y: 400.0
+            })
+        );
+        assert_eq!(switcher.jump("c", &clients), None);
+        assert!(
+            matches!(switcher.cycle(&clients), Motion::Switch { screen, .. } if screen == "server")
+        );
+        assert!(matches!(switcher.cycle(&clients), Motion::Switch { screen, .. } if screen == "a"));
+        switcher.take_warp();
+
+        //锁定后到达边缘不切换
+        switcher.set_locked(true);
+        assert!(matches!(
+            switcher.on_move(360.0, 540.0, &clients),
+            Motion::Remote { x, .. } if x == 0.0
+        ));
+        switcher.set_locked(false);
+        assert!(matches!(
+            switcher.on_move(350.0, 540.0, &clients),
+            Motion::Switch { screen, .. } if screen == "server"
+        ));
+        switcher.take_warp();
+        switcher.jump("b", &clients);
+        assert!(matches!(switcher.home(), Motion::Switch { screen, .. } if screen == "server"));
+        assert!(switcher.is_local());
+    }
+
+    #[test]
+    fn test_recenter() {
+        let clients = vec![client("a", 1)];
+        let mut switcher = EdgeSwitcher::new(Layout::default(), "server", Display::new(1920, 1080));
+        switcher.jump("a", &clients);
+        assert_eq!(switcher.take_warp(), Some((960.0, 540.0)));
+
+        //复位后收到光标在中心的移动事件，偏移量为0，不再复位
+        assert!(matches!(
+            switcher.on_move(960.0, 540.0, &clients),
+            Motion::Remote { dx, dy, .. } if dx == 0.0 && dy == 0.0
+        ));
+        switcher.recenter();
+        assert_eq!(switcher.take_warp(), None);
+
+        assert!(matches!(
+            switcher.on_move(970.0, 540.0, &clients),
+            Motion::Remote { dx, .. } if dx == 10.0
+        ));
+        switcher.recenter();
+        assert_eq!(switcher.take_warp(), Some((960.0, 540.0)));
+    }
+
+    #[test]
+    fn test_dwell_tick() {
+        let clients = vec![client("a", 1)];
+        let mut switcher = EdgeSwitcher::new(Layout::default(), "server", Display::new(1920, 1080));
+        switcher.set_guard(ConfigEdgeGuard {
+            dwell: 200,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert_eq!(switcher.tick(&clients, now), None);
+        assert_eq!(
+            switcher.on_move_at(1919.0, 540.0, &clients, now),
+            Motion::Local
+        );
+        //光标停在边缘不再移动，定时检查到达停留时间后切换
+        assert_eq!(
+            switcher.tick(&clients, now + Duration::from_millis(100)),
+            None
+        );
+        assert!(matches!(
+            switcher.tick(&clients, now + Duration::from_millis(200)),
+            Some(Motion::Switch { screen, .. }) if screen == "a"
+        ));
+        assert_eq!(
+            switcher.tick(&clients, now + Duration::from_millis(300)),
+            None
+        );
+        //客户端断开后回到服务器屏幕
+        assert!(matches!(
+            switcher.tick(&[], now),
+            Some(Motion::Switch { screen, .. }) if screen == "server"
+        ));
+        assert_eq!(switcher.tick(&[], now), None);
+    }
+
+    #[test]
+    fn test_lock_key() {
+        //无法读取切换状态时按释放次数切换
+        let mut lock = LockKey::with_query(Some(KeyMouse::ScrollLock), |_| None);
+        assert!(!lock.on_key(KeyMouse::KeyA, true));
+        assert!(!lock.is_on());
+        assert!(lock.on_key(KeyMouse::ScrollLock, true));
+        assert!(!lock.is_on());
+        assert!(lock.on_key(KeyMouse::ScrollLock, false));
+        assert!(lock.is_on());
+        lock.on_key(KeyMouse::ScrollLock, true);
+        lock.on_key(KeyMouse::ScrollLock, false);
+        assert!(!lock.is_on());
+        assert!(!LockKey::new(None).is_on());
+
+        //启动前已经打开
+        static ON: AtomicBool = AtomicBool::new(true);
+        let mut lock = LockKey::with_query(Some(KeyMouse::ScrollLock), |_| {
+            Some(ON.load(Ordering::Relaxed))
+        });
+        assert!(lock.is_on());
+        ON.store(false, Ordering::Relaxed);
+        lock.on_key(KeyMouse::ScrollLock, true);
+        assert!(lock.is_on());
+        lock.on_key(KeyMouse::ScrollLock, false);
+        assert!(!lock.is_on());
+    }
+
+    #[test]
+    fn test_edge_range() {
+        //客户端只与服务器右侧边缘的上半部分相接
+        let layout = Layout::new(&[ConfigLayoutLink {
+            from: "server".to_string(),
+            direction: ConfigClientDirection::Right,
+            to: "a".to_string(),
+            offset: 0,
+            start: 0.0,
+            end: 50.0,
+        }]);
+        let clients = vec![client("a", 1)];
+        let mut switcher = EdgeSwitcher::new(layout, "server", Display::new(1920, 1080));
+        assert_eq!(switcher.on_move(1915.0, 800.0, &clients), Motion::Local);
+        assert_eq!(
+            switcher.on_move(1915.0, 200.0, &clients),
+            Motion::Switch {
+                screen: "a".to_string(),
+                x: 10.0,
+                y: 200.0 / 1080.0 * 800.0
+            }
+        );
+        switcher.take_warp();
+
+        //反向切换时按进入服务器屏幕的位置判断
+        assert!(matches!(
+            switcher.on_move(960.0, 1040.0, &clients),
+            Motion::Remote { .. }
+        ));
+        assert!(matches!(
+            switcher.on_move(360.0, 1040.0, &clients),
+            Motion::Remote { x, .. } if x == 0.0
+        ));
+        assert!(matches!(
+            switcher.on_move(360.0, 540.0, &clients),
+            Motion::Switch { screen, .. } if screen == "server"
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::{Duration, Instant};
+
+use crate::{ConfigClientDirection, ConfigEdgeGuard, Display};
+
+/// 屏幕边缘切换条件
+///
+/// 光标到达边缘后需要满足停留时间、两次触碰、不在角落、未按住鼠标按钮等条件才切换屏幕，
+/// 防止操作靠近边缘的滚动条时误切换。
+#[derive(Debug, Default)]
+pub struct EdgeGuard {
+    config: ConfigEdgeGuard,
+    /// 当前到达的边缘及到达时间
+    arrived: Option<(ConfigClientDirection, Instant)>,
+    /// 上一次到达的边缘及到达时间，用于判断两次触碰
+    last_tap: Option<(ConfigClientDirection, Instant)>,
+    /// 两次触碰已满足，本次停留在边缘期间可以切换
+    tapped: bool,
+    /// 是否按住了鼠标按钮
+    button_held: bool,
+}
+
+impl EdgeGuard {
+    pub fn new(config: ConfigEdgeGuard) -> Self {
+        EdgeGuard {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// 更新鼠标按钮按住状态
+    pub fn set_button_held(&mut self, held: bool) {
+        self.button_held = held;
+    }
+
+    /// 光标位于`display`的`direction`边缘（None为不在边缘）时是否允许切换
+    pub fn allow(
+        &mut self,
+        direction: Option<ConfigClientDirection>,
+        (x, y): (f64, f64),
+        display: &Display,
+        now: Instant,
+    ) -> bool {
+        let direction = match direction {
+            Some(direction) => direction,
+            None => {
+                self.arrived = None;
+                return false;
+            }
+        };
+        let since = match self.arrived {
+            Some((arrived, since)) if arrived == direction => since,
+            _ => {
+                self.arrive(direction, now);
+                now
+            }
+        };
+
+        //按住按钮或在角落内时重新计时，离开后需要重新停留
+        if (self.button_held && !self.config.drag) || self.in_corner(direction, (x, y), display) {
+            self.arrived = Some((direction, now));
+            return false;
+        }
+        if self.config.double_tap && !self.tapped {
+            return false;
+        }
+        now.duration_since(since) >= Duration::from_millis(self.config.dwell)
+    }
+
+    /// 光标是否停留在边缘等待停留时间，等待期间需要定时调用[`allow`](Self::allow)
+    pub fn is_waiting(&self) -> bool {
+        self.arrived.is_some() && self.config.dwell > 0
+    }
+
+    /// 切换屏幕后重置状态
+    pub fn reset(&mut self) {
+        self.arrived = None;
+        self.last_tap = None;
+        self.tapped = false;
+    }
+
+    /// 光标刚到达边缘，检查是否为规定时间内的第二次触碰
+    fn arrive(&mut self, direction: ConfigClientDirection, now: Instant) {
+        let interval = Duration::from_millis(self.config.double_tap_interval);
+        self.tapped = matches!(
+            self.last_tap,
+            Some((last, at)) if last == direction && now.duration_since(at) <= interval
+        );
+        self.arrived = Some((direction, now));
+        self.last_tap = Some((direction, now));
+    }
+
+    /// 光标是否在边缘两端的角落内
+    fn in_corner(
+        &self,
+        direction: ConfigClientDirection,
+        (x, y): (f64, f64),
+        display: &Display,
+    ) -> bool {
+        let corner = self.config.corner as f64;
+        if corner <= 0.0 {
+            return false;
+        }
+        let (left, top, right, bottom) = display
+            .monitor_at(x, y)
+            .map(|m| m.bounds())
+            .unwrap_or_else(|| display.bounds());
+        match direction {
+            ConfigClientDirection::Left | ConfigClientDirection::Right => {
+                y < top + corner || y >= bottom - corner
+            }
+            ConfigClientDirection::Up | ConfigClientDirection::Down => {
+                x < left + corner || x >= right - corner
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EdgeGuard;
+    use crate::{ConfigClientDirection, ConfigEdgeGuard, Display};
+    use std::time::{Duration, Instant};
+
+    const RIGHT: Option<ConfigClientDirection> = Some(ConfigClientDirection::Right);
+
+    #[test]
+    fn test_dwell() {
+        let display = Display::new(1920, 1080);
+        let mut guard = EdgeGuard::new(ConfigEdgeGuard {
+            dwell: 200,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, now));
+        let later = now + Duration::from_millis(100);
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+        assert!(guard.is_waiting());
+        let later = now + Duration::from_millis(200);
+        assert!(guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+        //离开边缘后重新计时
+        assert!(!guard.allow(None, (1800.0, 500.0), &display, later));
+        assert!(!guard.is_waiting());
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+    }
+
+    #[test]
+    fn test_double_tap() {
+        let display = Display::new(1920, 1080);
+        let mut guard = EdgeGuard::new(ConfigEdgeGuard {
+            double_tap: true,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, now));
+        assert!(!guard.allow(None, (1800.0, 500.0), &display, now));
+        let later = now + Duration::from_millis(300);
+        assert!(guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+
+        //间隔太久不算两次触碰
+        guard.reset();
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, now));
+        assert!(!guard.allow(None, (1800.0, 500.0), &display, now));
+        let later = now + Duration::from_secs(2);
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+    }
+
+    #[test]
+    fn test_corner_and_button() {
+        let display = Display::new(1920, 1080);
+        let mut guard = EdgeGuard::new(ConfigEdgeGuard {
+            corner: 50,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(!guard.allow(RIGHT, (1915.0, 20.0), &display, now));
+        assert!(!guard.allow(RIGHT, (1915.0, 1060.0), &display, now));
+        assert!(guard.allow(RIGHT, (1915.0, 500.0), &display, now));
+
+        guard.set_button_held(true);
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, now));
+        let mut guard = EdgeGuard::new(ConfigEdgeGuard {
+            drag: true,
+            ..Default::default()
+        });
+        guard.set_button_held(true);
+        assert!(guard.allow(RIGHT, (1915.0, 500.0), &display, now));
+    }
+
+    #[test]
+    fn test_corner_dwell() {
+        let display = Display::new(1920, 1080);
+        let mut guard = EdgeGuard::new(ConfigEdgeGuard {
+            dwell: 200,
+            corner: 50,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        //停在角落的时间不计入停留时间
+        assert!(!guard.allow(RIGHT, (1915.0, 20.0), &display, now));
+        let later = now + Duration::from_millis(300);
+        assert!(!guard.allow(RIGHT, (1915.0, 20.0), &display, later));
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+        let later = later + Duration::from_millis(200);
+        assert!(guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+
+        //松开按钮后重新计时
+        guard.reset();
+        guard.set_button_held(true);
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, now));
+        let later = now + Duration::from_millis(300);
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+        guard.set_button_held(false);
+        assert!(!guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+        let later = later + Duration::from_millis(200);
+        assert!(guard.allow(RIGHT, (1915.0, 500.0), &display, later));
+    }
+}
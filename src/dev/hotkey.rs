@@ -0,0 +1,144 @@
+use crate::{net::protocol::KeyMouse, ConfigHotkey, ConfigHotkeyAction};
+
+/// 按键事件的处理结果
+#[derive(Debug, PartialEq)]
+pub enum HotkeyEvent {
+    /// 不是热键，正常转发
+    Forward,
+    /// 热键占用的按键，不转发
+    Consumed,
+    /// 触发热键，`release`为触发前已转发、需要在当前屏幕上释放的按键
+    Triggered {
+        action: ConfigHotkeyAction,
+        release: Vec<KeyMouse>,
+    },
+}
+
+/// 服务器全局热键
+///
+/// 触发热键的按键直到释放前都不再转发，防止热键泄漏到客户端。
+pub struct Hotkeys {
+    hotkeys: Vec<ConfigHotkey>,
+    /// 已按下的按键
+    pressed: Vec<KeyMouse>,
+    /// 被热键占用、尚未释放的按键
+    consumed: Vec<KeyMouse>,
+}
+
+impl Hotkeys {
+    pub fn new(mut hotkeys: Vec<ConfigHotkey>) -> Self {
+        hotkeys.retain(|hotkey| !hotkey.keys.is_empty());
+        //按键多的热键优先，Ctrl+Alt+1不会被Alt+1抢先触发
+        hotkeys.sort_by_key(|hotkey| std::cmp::Reverse(hotkey.keys.len()));
+        Hotkeys {
+            hotkeys,
+            pressed: vec![],
+            consumed: vec![],
+        }
+    }
+
+    /// 处理按键按下/释放，`press`为`true`时为按下
+    pub fn on_key(&mut self, key: KeyMouse, press: bool) -> HotkeyEvent {
+        if !press {
+            self.pressed.retain(|k| *k != key);
+            if self.consumed.contains(&key) {
+                self.consumed.retain(|k| *k != key);
+                return HotkeyEvent::Consumed;
+            }
+            return HotkeyEvent::Forward;
+        }
+
+        let repeat = self.pressed.contains(&key);
+        if !repeat {
+            self.pressed.push(key);
+        }
+        let hotkey = self.hotkeys.iter().find(|hotkey| {
+            hotkey.keys.contains(&key) && hotkey.keys.iter().all(|k| self.pressed.contains(k))
+        });
+        match hotkey {
+            Some(hotkey) if !repeat => {
+                let release = hotkey
+                    .keys
+                    .iter()
+                    .filter(|k| **k != key && !self.consumed.contains(k))
+                    .copied()
+                    .collect();
+                for k in &hotkey.keys {
+                    if !self.consumed.contains(k) {
+                        self.consumed.push(*k);
+                    }
+                }
+                HotkeyEvent::Triggered {
+                    action: hotkey.action.clone(),
+                    release,
+                }
+            }
+            _ if self.consumed.contains(&key) => HotkeyEvent::Consumed,
+            _ => HotkeyEvent::Forward,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HotkeyEvent, Hotkeys};
+    use crate::{net::protocol::KeyMouse, ConfigHotkey, ConfigHotkeyAction};
+
+    #[test]
+    fn test_hotkey_not_leaked() {
+        use KeyMouse::*;
+        let mut hotkeys = Hotkeys::new(vec![
+            ConfigHotkey {
+                keys: vec![ControlLeft, Alt, Num1],
+                action: ConfigHotkeyAction::Switch {
+                    screen: "a".to_string(),
+                },
+            },
+            ConfigHotkey {
+                keys: vec![ControlLeft, Alt, Tab],
+                action: ConfigHotkeyAction::Cycle,
+            },
+        ]);
+        assert_eq!(hotkeys.on_key(ControlLeft, true), HotkeyEvent::Forward);
+        assert_eq!(hotkeys.on_key(Alt, true), HotkeyEvent::Forward);
+        assert_eq!(
+            hotkeys.on_key(Num1, true),
+            HotkeyEvent::Triggered {
+                action: ConfigHotkeyAction::Switch {
+                    screen: "a".to_string()
+                },
+                release: vec![ControlLeft, Alt],
+            }
+        );
+        //自动重复和释放都不转发
+        assert_eq!(hotkeys.on_key(Num1, true), HotkeyEvent::Consumed);
+        assert_eq!(hotkeys.on_key(Num1, false), HotkeyEvent::Consumed);
+        //按住修饰键再次触发热键，不需要再释放修饰键
+        assert_eq!(
+            hotkeys.on_key(Tab, true),
+            HotkeyEvent::Triggered {
+                action: ConfigHotkeyAction::Cycle,
+                release: vec![],
+            }
+        );
+        assert_eq!(hotkeys.on_key(Tab, false), HotkeyEvent::Consumed);
+        assert_eq!(hotkeys.on_key(Alt, false), HotkeyEvent::Consumed);
+        assert_eq!(hotkeys.on_key(ControlLeft, false), HotkeyEvent::Consumed);
+        assert_eq!(hotkeys.on_key(Num1, true), HotkeyEvent::Forward);
+    }
+
+    #[test]
+    fn test_hotkey_config() {
+        let hotkeys: Vec<ConfigHotkey> = serde_yaml::from_str(
+            "- keys: [ControlLeft, Alt, Num1]\n  action: switch\n  screen: a\n- keys: [ScrollLock]\n  action: lock\n",
+        )
+        .unwrap();
+        assert_eq!(
+            hotkeys[0].action,
+            ConfigHotkeyAction::Switch {
+                screen: "a".to_string()
+            }
+        );
+        assert_eq!(hotkeys[1].action, ConfigHotkeyAction::Lock);
+    }
+}
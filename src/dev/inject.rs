@@ -0,0 +1,341 @@
+//! rdev不支持的模拟输入，直接调用各平台接口
+
+pub use platform::{button, move_relative, scroll, text};
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use anyhow::{anyhow, Result};
+    use std::mem::size_of;
+    use winapi::um::winuser::{
+        SendInput, INPUT, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, KEYEVENTF_KEYUP,
+        KEYEVENTF_UNICODE, MOUSEEVENTF_HWHEEL, MOUSEEVENTF_MOVE, MOUSEEVENTF_WHEEL,
+        MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, MOUSEINPUT,
+    };
+
+    /// 相对移动鼠标，不经过绝对坐标换算，捕获光标的程序（游戏、三维软件）也能收到移动
+    pub fn move_relative(dx: f64, dy: f64) -> Result<()> {
+        send_mouse_input(dx.round() as i32, dy.round() as i32, 0, MOUSEEVENTF_MOVE)
+    }
+
+    /// 滚动滚轮，`delta`为高精度滚动量（`WHEEL_DELTA`为一格），正数向上/向右
+    pub fn scroll(horizontal: bool, delta: i64) -> Result<()> {
+        let flags = if horizontal {
+            MOUSEEVENTF_HWHEEL
+        } else {
+            MOUSEEVENTF_WHEEL
+        };
+        send_mouse_input(0, 0, delta as i32 as u32, flags)
+    }
+
+    /// 按下或释放鼠标附加按钮，`code`为XBUTTON编号
+    pub fn button(code: u8, press: bool) -> Result<()> {
+        let flags = if press {
+            MOUSEEVENTF_XDOWN
+        } else {
+            MOUSEEVENTF_XUP
+        };
+        send_mouse_input(0, 0, code as u32, flags)
+    }
+
+    /// 输入文本，不经过键盘布局
+    pub fn text(text: &str) -> Result<()> {
+        for unit in text.encode_utf16() {
+            send_key_input(unit, KEYEVENTF_UNICODE)?;
+            send_key_input(unit, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP)?;
+        }
+        Ok(())
+    }
+
+    fn send_key_input(scan: u16, flags: u32) -> Result<()> {
+        let mut input = INPUT {
+            type_: INPUT_KEYBOARD,
+            u: unsafe { std::mem::zeroed() },
+        };
+        unsafe {
+            *input.u.ki_mut() = KEYBDINPUT {
+                wVk: 0,
+                wScan: scan,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            };
+        }
+        let sent = unsafe { SendInput(1, &mut input, size_of::<INPUT>() as i32) };
+        if sent != 1 {
+            return Err(anyhow!("SendInput失败"));
+        }
+        Ok(())
+    }
+
+    fn send_mouse_input(dx: i32, dy: i32, data: u32, flags: u32) -> Result<()> {
+        let mut input = INPUT {
+            type_: INPUT_MOUSE,
+            u: unsafe { std::mem::zeroed() },
+        };
+        unsafe {
+            *input.u.mi_mut() = MOUSEINPUT {
+                dx,
+                dy,
+                mouseData: data,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            };
+        }
+        let sent = unsafe { SendInput(1, &mut input, size_of::<INPUT>() as i32) };
+        if sent != 1 {
+            return Err(anyhow!("SendInput失败"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use anyhow::{anyhow, Result};
+    use std::{cell::RefCell, ptr::null, sync::Mutex, thread, time::Duration};
+    use x11::{xlib, xtest};
+
+    use crate::net::protocol::WHEEL_DELTA;
+
+    /// X11只能按格滚动，不足一格的高精度滚动量累计到下次
+    static WHEEL_REMAINDER: Mutex<(i64, i64)> = Mutex::new((0, 0));
+    /// 恢复临时按键映射前等待应用程序处理完按键事件
+    const REMAP_RESTORE_DELAY: Duration = Duration::from_millis(10);
+
+    /// X11连接，第一次模拟输入时打开，线程退出时关闭
+    struct Connection(*mut xlib::Display);
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            unsafe {
+                xlib::XCloseDisplay(self.0);
+            }
+        }
+    }
+
+    thread_local! {
+        //Xlib连接不能跨线程使用，每个模拟输入的线程一个连接
+        static CONNECTION: RefCell<Option<Connection>> = const { RefCell::new(None) };
+    }
+
+    /// 相对移动鼠标，不经过绝对坐标换算，捕获光标的程序（游戏、三维软件）也能收到移动
+    pub fn move_relative(dx: f64, dy: f64) -> Result<()> {
+        with_display(|display| unsafe {
+            xtest::XTestFakeRelativeMotionEvent(
+                display,
+                -1,
+                dx.round() as i32,
+                dy.round() as i32,
+                0,
+            )
+        })
+    }
+
+    /// 滚动滚轮，`delta`为高精度滚动量（`WHEEL_DELTA`为一格），正数向上/向右
+    pub fn scroll(horizontal: bool, delta: i64) -> Result<()> {
+        let notches = {
+            let mut remainder = WHEEL_REMAINDER
+                .lock()
+                .map_err(|e| anyhow!("wheel remainder lock error: {}", e))?;
+            let total = if horizontal {
+                &mut remainder.0
+            } else {
+                &mut remainder.1
+            };
+            *total += delta;
+            let notches = *total / WHEEL_DELTA;
+            *total %= WHEEL_DELTA;
+            notches
+        };
+        //按钮4、5为向上、向下滚动，6、7为向左、向右滚动
+        let button = match (horizontal, notches > 0) {
+            (false, true) => 4,
+            (false, false) => 5,
+            (true, false) => 6,
+            (true, true) => 7,
+        };
+        if notches == 0 {
+            return Ok(());
+        }
+        with_display(|display| unsafe {
+            (0..notches.abs()).fold(1, |result, _| {
+                result
+                    & xtest::XTestFakeButtonEvent(display, button, xlib::True, 0)
+                    & xtest::XTestFakeButtonEvent(display, button, xlib::False, 0)
+            })
+        })
+    }
+
+    /// 按下或释放鼠标附加按钮，`code`为X11按钮编号
+    pub fn button(code: u8, press: bool) -> Result<()> {
+        let state = if press { xlib::True } else { xlib::False };
+        with_display(|display| unsafe {
+            xtest::XTestFakeButtonEvent(display, code as u32, state, 0)
+        })
+    }
+
+    /// 输入文本，不经过键盘布局
+    ///
+    /// X11只能模拟keycode，临时把一个未使用的keycode映射为字符对应的keysym再模拟按键。
+    pub fn text(text: &str) -> Result<()> {
+        with_display(|display| unsafe {
+            let (mut min, mut max) = (0, 0);
+            xlib::XDisplayKeycodes(display, &mut min, &mut max);
+            let mut per_keycode = 0;
+            let mapping =
+                xlib::XGetKeyboardMapping(display, min as u8, max - min + 1, &mut per_keycode);
+            if mapping.is_null() {
+                return 0;
+            }
+            //没有映射任何keysym的keycode
+            let spare = (min..=max).find(|code| {
+                let start = ((code - min) * per_keycode) as usize;
+                (start..start + per_keycode as usize).all(|i| *mapping.add(i) == 0)
+            });
+            xlib::XFree(mapping as *mut _);
+            let spare = match spare {
+                Some(spare) => spare,
+                None => return 0,
+            };
+            for c in text.chars() {
+                let mut keysym = keysym(c);
+                xlib::XChangeKeyboardMapping(display, spare, 1, &mut keysym, 1);
+                xlib::XSync(display, xlib::False);
+                xtest::XTestFakeKeyEvent(display, spare as u32, xlib::True, 0);
+                xtest::XTestFakeKeyEvent(display, spare as u32, xlib::False, 0);
+                //按键事件处理完后才能修改映射，否则可能按修改后的映射解释按键
+                xlib::XSync(display, xlib::False);
+                thread::sleep(REMAP_RESTORE_DELAY);
+            }
+            let mut no_symbol = 0;
+            xlib::XChangeKeyboardMapping(display, spare, 1, &mut no_symbol, 1);
+            xlib::XSync(display, xlib::False);
+            1
+        })
+    }
+
+    /// 字符对应的keysym，Latin-1字符与编码相同，其他字符为0x01000000加Unicode编码
+    fn keysym(c: char) -> xlib::KeySym {
+        match c as u32 {
+            code @ (0x20..=0x7E | 0xA0..=0xFF) => code as xlib::KeySym,
+            code => (0x0100_0000 | code) as xlib::KeySym,
+        }
+    }
+
+    /// 使用当前线程的X11连接模拟输入，连接在多次调用间复用
+    fn with_display<F>(f: F) -> Result<()>
+    where
+        F: FnOnce(*mut xlib::Display) -> i32,
+    {
+        CONNECTION.with(|connection| {
+            let mut connection = connection.borrow_mut();
+            let display = match connection.as_ref() {
+                Some(connection) => connection.0,
+                None => {
+                    let display = unsafe { xlib::XOpenDisplay(null()) };
+                    if display.is_null() {
+                        return Err(anyhow!("打开X11 Display失败"));
+                    }
+                    *connection = Some(Connection(display));
+                    display
+                }
+            };
+            let result = unsafe {
+                let result = f(display);
+                xlib::XFlush(display);
+                result
+            };
+            if result == 0 {
+                return Err(anyhow!("XTest模拟输入失败"));
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use anyhow::{anyhow, Result};
+    use core_graphics::{
+        event::{
+            CGEvent, CGEventTapLocation, CGEventType, CGMouseButton, EventField, ScrollEventUnit,
+        },
+        event_source::{CGEventSource, CGEventSourceStateID},
+        geometry::CGPoint,
+    };
+
+    use crate::net::protocol::WHEEL_DELTA;
+
+    /// 滚轮滚动一格对应的像素
+    const WHEEL_PIXELS: i64 = 10;
+
+    /// 相对移动鼠标，不经过绝对坐标换算，捕获光标的程序（游戏、三维软件）也能收到移动
+    pub fn move_relative(dx: f64, dy: f64) -> Result<()> {
+        let location = CGEvent::new(source()?)
+            .map_err(|_| anyhow!("获取光标位置失败"))?
+            .location();
+        let event = CGEvent::new_mouse_event(
+            source()?,
+            CGEventType::MouseMoved,
+            CGPoint::new(location.x + dx, location.y + dy),
+            CGMouseButton::Left,
+        )
+        .map_err(|_| anyhow!("创建鼠标事件失败"))?;
+        event.set_integer_value_field(EventField::MOUSE_EVENT_DELTA_X, dx.round() as i64);
+        event.set_integer_value_field(EventField::MOUSE_EVENT_DELTA_Y, dy.round() as i64);
+        event.post(CGEventTapLocation::HID);
+        Ok(())
+    }
+
+    /// 滚动滚轮，`delta`为高精度滚动量（`WHEEL_DELTA`为一格），正数向上/向右
+    pub fn scroll(horizontal: bool, delta: i64) -> Result<()> {
+        let pixels = (delta * WHEEL_PIXELS / WHEEL_DELTA) as i32;
+        let (vertical, horizontal) = if horizontal { (0, pixels) } else { (pixels, 0) };
+        let event = CGEvent::new_scroll_event(
+            source()?,
+            ScrollEventUnit::PIXEL,
+            2,
+            vertical,
+            horizontal,
+            0,
+        )
+        .map_err(|_| anyhow!("创建滚轮事件失败"))?;
+        event.post(CGEventTapLocation::HID);
+        Ok(())
+    }
+
+    /// 按下或释放鼠标附加按钮，`code`为macOS按钮编号
+    pub fn button(code: u8, press: bool) -> Result<()> {
+        let location = CGEvent::new(source()?)
+            .map_err(|_| anyhow!("获取光标位置失败"))?
+            .location();
+        let event_type = if press {
+            CGEventType::OtherMouseDown
+        } else {
+            CGEventType::OtherMouseUp
+        };
+        let event =
+            CGEvent::new_mouse_event(source()?, event_type, location, CGMouseButton::Center)
+                .map_err(|_| anyhow!("创建鼠标事件失败"))?;
+        event.set_integer_value_field(EventField::MOUSE_EVENT_BUTTON_NUMBER, code as i64);
+        event.post(CGEventTapLocation::HID);
+        Ok(())
+    }
+
+    /// 输入文本，不经过键盘布局
+    pub fn text(text: &str) -> Result<()> {
+        for press in [true, false] {
+            let event = CGEvent::new_keyboard_event(source()?, 0, press)
+                .map_err(|_| anyhow!("创建键盘事件失败"))?;
+            event.set_string(text);
+            event.post(CGEventTapLocation::HID);
+        }
+        Ok(())
+    }
+
+    fn source() -> Result<CGEventSource> {
+        CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+            .map_err(|_| anyhow!("创建CGEventSource失败"))
+    }
+}
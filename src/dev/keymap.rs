@@ -0,0 +1,201 @@
+//! 按客户端配置的按键重映射
+//!
+//! 根据服务器上按住的按键计算客户端应该按住的按键，与已发送的按键比较后只发送差异，
+//! 组合键规则（如Ctrl+C → Meta+C）生效、失效时会先释放、再按下相应的按键。
+use anyhow::{anyhow, Result};
+
+use crate::{net::protocol::KeyMouse, ConfigKeymapRule};
+
+/// 单个客户端的按键重映射状态
+#[derive(Debug, Default)]
+pub struct Keymap {
+    /// 重映射规则，按键多的组合键优先
+    rules: Vec<ConfigKeymapRule>,
+    /// 服务器上按住的按键，按按下顺序排列
+    held: Vec<KeyMouse>,
+    /// 已发送给客户端、尚未释放的按键
+    sent: Vec<KeyMouse>,
+}
+
+impl Keymap {
+    pub fn new(rules: &[ConfigKeymapRule]) -> Result<Self> {
+        for (i, rule) in rules.iter().enumerate() {
+            if rule.from.is_empty() || rule.to.is_empty() {
+                return Err(anyhow!("keymap[{}]: from and to must not be empty", i));
+            }
+            let duplicate = rules[..i].iter().any(|r| {
+                r.from.len() == rule.from.len() && r.from.iter().all(|k| rule.from.contains(k))
+            });
+            if duplicate {
+                return Err(anyhow!("keymap[{}]: duplicate rule {:?}", i, rule.from));
+            }
+        }
+        let mut rules = rules.to_vec();
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.from.len()));
+        Ok(Keymap {
+            rules,
+            ..Default::default()
+        })
+    }
+
+    /// 是否没有重映射规则
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// 按键按下/释放转换为发送给客户端的按键事件，`true`为按下
+    pub fn map(&mut self, key: KeyMouse, press: bool) -> Vec<(KeyMouse, bool)> {
+        if press && self.held.contains(&key) {
+            //按住按键自动重复时，重复发送该按键对应的最后一个按键
+            return self
+                .groups()
+                .into_iter()
+                .find(|(from, _)| from.contains(&key))
+                .and_then(|(_, to)| to.last().copied())
+                .map(|k| vec![(k, true)])
+                .unwrap_or_default();
+        }
+        if press {
+            self.held.push(key);
+        } else if self.held.contains(&key) {
+            self.held.retain(|k| *k != key);
+        } else {
+            //映射开始前按下的按键，原样释放
+            self.sent.retain(|k| *k != key);
+            return vec![(key, false)];
+        }
+
+        let mut desired: Vec<KeyMouse> = vec![];
+        for key in self.groups().into_iter().flat_map(|(_, to)| to) {
+            if !desired.contains(&key) {
+                desired.push(key);
+            }
+        }
+        let mut events: Vec<(KeyMouse, bool)> = self
+            .sent
+            .iter()
+            .rev()
+            .filter(|k| !desired.contains(k))
+            .map(|k| (*k, false))
+            .collect();
+        events.extend(
+            desired
+                .iter()
+                .filter(|k| !self.sent.contains(k))
+                .map(|k| (*k, true)),
+        );
+        self.sent.retain(|k| desired.contains(k));
+        for key in desired {
+            if !self.sent.contains(&key) {
+                self.sent.push(key);
+            }
+        }
+        events
+    }
+
+    /// 切换屏幕时重置状态，返回已发送、尚未释放的按键，按按下的相反顺序排列
+    pub fn reset(&mut self) -> Vec<KeyMouse> {
+        self.held.clear();
+        self.sent.drain(..).rev().collect()
+    }
+
+    /// 按住的按键按规则分组，返回(服务器按键, 客户端按键)，按按下顺序排列
+    fn groups(&self) -> Vec<(Vec<KeyMouse>, Vec<KeyMouse>)> {
+        let mut remaining = self.held.clone();
+        let mut groups = vec![];
+        for rule in &self.rules {
+            if rule.from.iter().all(|k| remaining.contains(k)) {
+                remaining.retain(|k| !rule.from.contains(k));
+                groups.push((rule.from.clone(), rule.to.clone()));
+            }
+        }
+        groups.extend(remaining.into_iter().map(|k| (vec![k], vec![k])));
+        let pressed_at = |from: &Vec<KeyMouse>| {
+            from.iter()
+                .filter_map(|k| self.held.iter().position(|h| h == k))
+                .min()
+        };
+        groups.sort_by_key(|(from, _)| pressed_at(from));
+        groups
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keymap;
+    use crate::{net::protocol::KeyMouse, ConfigKeymapRule};
+
+    fn rule(from: &[KeyMouse], to: &[KeyMouse]) -> ConfigKeymapRule {
+        ConfigKeymapRule {
+            from: from.to_vec(),
+            to: to.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_swap_keys() {
+        use KeyMouse::*;
+        let mut keymap = Keymap::new(&[
+            rule(&[ControlLeft], &[MetaLeft]),
+            rule(&[MetaLeft], &[ControlLeft]),
+            rule(&[CapsLock], &[Escape]),
+        ])
+        .unwrap();
+        assert_eq!(keymap.map(ControlLeft, true), vec![(MetaLeft, true)]);
+        assert_eq!(keymap.map(KeyA, true), vec![(KeyA, true)]);
+        assert_eq!(keymap.map(KeyA, true), vec![(KeyA, true)]);
+        assert_eq!(keymap.map(KeyA, false), vec![(KeyA, false)]);
+        assert_eq!(keymap.map(ControlLeft, false), vec![(MetaLeft, false)]);
+        assert_eq!(keymap.map(CapsLock, true), vec![(Escape, true)]);
+        assert_eq!(keymap.map(CapsLock, false), vec![(Escape, false)]);
+    }
+
+    #[test]
+    fn test_chord() {
+        use KeyMouse::*;
+        let mut keymap = Keymap::new(&[rule(&[ControlLeft, KeyC], &[MetaLeft, KeyC])]).unwrap();
+        assert_eq!(keymap.map(ControlLeft, true), vec![(ControlLeft, true)]);
+        assert_eq!(
+            keymap.map(KeyC, true),
+            vec![(ControlLeft, false), (MetaLeft, true), (KeyC, true)]
+        );
+        assert_eq!(keymap.map(KeyC, true), vec![(KeyC, true)]);
+        assert_eq!(
+            keymap.map(KeyC, false),
+            vec![(KeyC, false), (MetaLeft, false), (ControlLeft, true)]
+        );
+        assert_eq!(keymap.map(ControlLeft, false), vec![(ControlLeft, false)]);
+        //映射开始前按下的按键原样释放
+        assert_eq!(keymap.map(KeyV, false), vec![(KeyV, false)]);
+    }
+
+    #[test]
+    fn test_reset() {
+        use KeyMouse::*;
+        let mut keymap = Keymap::new(&[rule(&[ControlLeft, KeyC], &[MetaLeft, KeyC])]).unwrap();
+        keymap.map(ControlLeft, true);
+        keymap.map(KeyC, true);
+        assert_eq!(keymap.reset(), vec![KeyC, MetaLeft]);
+        assert!(keymap.reset().is_empty());
+        //切换屏幕后释放的按键原样发送，不再触发组合键规则
+        assert_eq!(keymap.map(KeyC, false), vec![(KeyC, false)]);
+        assert_eq!(keymap.map(ControlLeft, false), vec![(ControlLeft, false)]);
+        assert_eq!(keymap.map(KeyC, true), vec![(KeyC, true)]);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        use KeyMouse::*;
+        assert!(Keymap::new(&[rule(&[], &[KeyA])]).is_err());
+        assert!(Keymap::new(&[
+            rule(&[ControlLeft, KeyC], &[MetaLeft, KeyC]),
+            rule(&[KeyC, ControlLeft], &[KeyX]),
+        ])
+        .is_err());
+        //未知的按键名字在解析配置时报错
+        let error = serde_yaml::from_str::<ConfigKeymapRule>("from: [Ctrl]\nto: [MetaLeft]")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Ctrl"), "{}", error);
+    }
+}
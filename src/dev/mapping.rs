@@ -0,0 +1,197 @@
+use crate::{dev::edge::EDGE_WIDTH, layout::EdgeOffset, ConfigClientDirection, Display, Monitor};
+
+/// 光标从`from`屏幕的`direction`边缘进入`to`屏幕时的坐标
+///
+/// 沿边缘方向按两侧屏幕的比例映射，分辨率不同的屏幕也会进入到相同的相对位置；
+/// 进入坐标放在边缘之外，防止立即切换回去。
+pub fn entry_point(
+    direction: ConfigClientDirection,
+    (x, y): (f64, f64),
+    from: &Display,
+    to: &Display,
+    offset: EdgeOffset,
+) -> (f64, f64) {
+    let (from_left, from_top, from_right, from_bottom) = from.bounds();
+    let (left, top, right, bottom) = to.bounds();
+    let (x, y) = match direction {
+        ConfigClientDirection::Left | ConfigClientDirection::Right => (
+            x,
+            proportional(y, (from_top, from_bottom), (top, bottom), offset),
+        ),
+        ConfigClientDirection::Up | ConfigClientDirection::Down => (
+            proportional(x, (from_left, from_right), (left, right), offset),
+            y,
+        ),
+    };
+    let (x, y) = (x.clamp(left, right - 1.0), y.clamp(top, bottom - 1.0));
+
+    //多显示器时外接矩形的边缘不一定有显示器，取移动方向上遇到的第一个显示器边缘
+    let monitors = to.monitors.iter().map(Monitor::bounds);
+    let entry = match direction {
+        ConfigClientDirection::Left => monitors
+            .filter(|(_, t, _, b)| y >= *t && y < *b)
+            .map(|(_, _, r, _)| r)
+            .reduce(f64::max)
+            .map(|r| (r - EDGE_WIDTH - 1.0, y)),
+        ConfigClientDirection::Right => monitors
+            .filter(|(_, t, _, b)| y >= *t && y < *b)
+            .map(|(l, _, _, _)| l)
+            .reduce(f64::min)
+            .map(|l| (l + EDGE_WIDTH, y)),
+        ConfigClientDirection::Up => monitors
+            .filter(|(l, _, r, _)| x >= *l && x < *r)
+            .map(|(_, _, _, b)| b)
+            .reduce(f64::max)
+            .map(|b| (x, b - EDGE_WIDTH - 1.0)),
+        ConfigClientDirection::Down => monitors
+            .filter(|(l, _, r, _)| x >= *l && x < *r)
+            .map(|(_, t, _, _)| t)
+            .reduce(f64::min)
+            .map(|t| (x, t + EDGE_WIDTH)),
+    };
+    let (x, y) = entry.unwrap_or((x, y));
+    to.clamp(x, y)
+}
+
+/// 按两侧显示器的缩放比例换算移动偏移量，光标在不同DPI的屏幕上移动相同的逻辑距离
+pub fn map_delta((dx, dy): (f64, f64), from_scale: f64, to_scale: f64) -> (f64, f64) {
+    if from_scale <= 0.0 || to_scale <= 0.0 {
+        return (dx, dy);
+    }
+    let ratio = to_scale / from_scale;
+    (dx * ratio, dy * ratio)
+}
+
+/// 坐标所在显示器的缩放比例，不在任何显示器上时使用主显示器
+pub fn scale_at(display: &Display, x: f64, y: f64) -> f64 {
+    display
+        .monitor_at(x, y)
+        .or_else(|| display.monitors.first())
+        .map(|m| m.scale)
+        .unwrap_or(1.0)
+}
+
+/// 坐标沿`direction`边缘方向在屏幕外接矩形中的比例位置，左/上端为0，右/下端为1
+pub fn edge_ratio(direction: ConfigClientDirection, (x, y): (f64, f64), display: &Display) -> f64 {
+    let (left, top, right, bottom) = display.bounds();
+    let (value, start, end) = match direction {
+        ConfigClientDirection::Left | ConfigClientDirection::Right => (y, top, bottom),
+        ConfigClientDirection::Up | ConfigClientDirection::Down => (x, left, right),
+    };
+    if end <= start {
+        return 0.0;
+    }
+    (value - start) / (end - start)
+}
+
+/// 将`value`在`from`范围内的相对位置映射到`to`范围，并减去偏移
+fn proportional(value: f64, from: (f64, f64), to: (f64, f64), offset: EdgeOffset) -> f64 {
+    let (from_len, to_len) = (from.1 - from.0, to.1 - to.0);
+    if from_len <= 0.0 || to_len <= 0.0 {
+        return value;
+    }
+    let offset = if offset.target {
+        offset.pixels / to_len
+    } else {
+        offset.pixels / from_len
+    };
+    let ratio = (value - from.0) / from_len - offset;
+    to.0 + ratio * to_len
+}
+
+#[cfg(test)]
+mod test {
+    use super::{entry_point, map_delta};
+    use crate::{layout::EdgeOffset, ConfigClientDirection, Display};
+
+    #[test]
+    fn test_entry_point_right() {
+        let (server, client) = (Display::new(3840, 2160), Display::new(1920, 1080));
+        let entry = entry_point(
+            ConfigClientDirection::Right,
+            (3835.0, 1080.0),
+            &server,
+            &client,
+            EdgeOffset::default(),
+        );
+        assert_eq!(entry, (10.0, 540.0));
+    }
+
+    #[test]
+    fn test_entry_point_left() {
+        let (server, client) = (Display::new(3840, 2160), Display::new(1920, 1080));
+        let entry = entry_point(
+            ConfigClientDirection::Left,
+            (0.0, 540.0),
+            &server,
+            &client,
+            EdgeOffset::default(),
+        );
+        assert_eq!(entry, (1909.0, 270.0));
+    }
+
+    #[test]
+    fn test_entry_point_up() {
+        let (server, client) = (Display::new(3840, 2160), Display::new(1920, 1080));
+        let entry = entry_point(
+            ConfigClientDirection::Up,
+            (960.0, 0.0),
+            &server,
+            &client,
+            EdgeOffset::default(),
+        );
+        assert_eq!(entry, (480.0, 1069.0));
+    }
+
+    #[test]
+    fn test_entry_point_down() {
+        let (server, client) = (Display::new(3840, 2160), Display::new(1920, 1080));
+        let entry = entry_point(
+            ConfigClientDirection::Down,
+            (3000.0, 2159.0),
+            &server,
+            &client,
+            EdgeOffset::default(),
+        );
+        assert_eq!(entry, (1500.0, 10.0));
+    }
+
+    #[test]
+    fn test_entry_point_offset() {
+        let (server, client) = (Display::new(3840, 2160), Display::new(1920, 1080));
+        //客户端屏幕物理位置偏下100像素
+        let offset = EdgeOffset {
+            pixels: 100.0,
+            target: true,
+        };
+        let entry = entry_point(
+            ConfigClientDirection::Right,
+            (3835.0, 1080.0),
+            &server,
+            &client,
+            offset,
+        );
+        assert_eq!((entry.0, entry.1.round()), (10.0, 440.0));
+
+        //反向移动回到服务器屏幕上相同的位置
+        let offset = EdgeOffset {
+            pixels: -100.0,
+            target: false,
+        };
+        let entry = entry_point(
+            ConfigClientDirection::Left,
+            (0.0, 440.0),
+            &client,
+            &server,
+            offset,
+        );
+        assert_eq!((entry.0, entry.1.round()), (3829.0, 1080.0));
+    }
+
+    #[test]
+    fn test_map_delta() {
+        assert_eq!(map_delta((30.0, -15.0), 1.5, 1.0), (20.0, -10.0));
+        assert_eq!(map_delta((30.0, -15.0), 1.0, 2.0), (60.0, -30.0));
+        assert_eq!(map_delta((30.0, -15.0), 0.0, 2.0), (30.0, -15.0));
+    }
+}
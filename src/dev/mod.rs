@@ -1,2 +1,10 @@
 pub mod client;
+pub mod edge;
+pub mod guard;
+pub mod hotkey;
+pub mod inject;
+pub mod keymap;
+pub mod mapping;
+pub mod reload;
 pub mod server;
+pub mod toggle;
@@ -0,0 +1,199 @@
+use crate::Config;
+use anyhow::Result;
+use log::{debug, error};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// 检查配置文件是否修改的间隔
+pub const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 配置文件监视，任一文件修改后通知重新加载
+///
+/// 轮询比较文件内容（修改时间精度不够时连续两次修改可能相同），
+/// 修改后等待一个间隔内不再变化才读取，避免读到写了一半的文件。
+pub struct ConfigWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    /// 启动监视线程，`paths`中的文件修改（包括创建、删除）后调用`on_change`
+    pub fn start<F>(paths: Vec<PathBuf>, interval: Duration, mut on_change: F) -> Result<Self>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let read = move || -> Vec<Option<Vec<u8>>> {
+            paths.iter().map(|path| fs::read(path).ok()).collect()
+        };
+        let mut last = read();
+        let thread = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("minput-reload".to_string())
+                .spawn(move || {
+                    let mut pending = None;
+                    while !stop.load(Ordering::Relaxed) {
+                        thread::sleep(interval);
+                        let current = read();
+                        if current == last {
+                            pending = None;
+                        } else if pending.as_ref() == Some(&current) {
+                            debug!("config file changed");
+                            last = current;
+                            pending = None;
+                            on_change();
+                        } else {
+                            pending = Some(current);
+                        }
+                    }
+                })?
+        };
+        Ok(ConfigWatcher {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// 停止并等待监视线程退出
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("config watcher thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 合并新配置：需要重启才能生效的字段保留原值，返回合并后的配置和这些字段的路径
+pub fn merge(old: &Config, mut new: Config) -> (Config, Vec<String>) {
+    let mut restart_required = vec![];
+    if new.mode != old.mode {
+        restart_required.push("mode".to_string());
+        new.mode = old.mode;
+    }
+    if new.display != old.display {
+        restart_required.push("display".to_string());
+        new.display = old.display.clone();
+    }
+    if new.keyboard_layout != old.keyboard_layout {
+        restart_required.push("keyboard_layout".to_string());
+        new.keyboard_layout = old.keyboard_layout.clone();
+    }
+    if new.metrics_port != old.metrics_port {
+        restart_required.push("metrics_port".to_string());
+        new.metrics_port = old.metrics_port;
+    }
+    match (&old.server, &mut new.server) {
+        (Some(old), Some(new)) => {
+            if new.name != old.name {
+                restart_required.push("server.name".to_string());
+                new.name = old.name.clone();
+            }
+            if new.ip != old.ip {
+                restart_required.push("server.ip".to_string());
+                new.ip = old.ip.clone();
+            }
+            if new.port != old.port {
+                restart_required.push("server.port".to_string());
+                new.port = old.port;
+            }
+            if new.control_socket != old.control_socket {
+                restart_required.push("server.control_socket".to_string());
+                new.control_socket = old.control_socket.clone();
+            }
+            if new.discovery != old.discovery || new.discovery_port != old.discovery_port {
+                restart_required.push("server.discovery".to_string());
+                new.discovery = old.discovery;
+                new.discovery_port = old.discovery_port;
+            }
+        }
+        //mode修改为client时保留原服务端配置
+        (Some(old), None) => new.server = Some(old.clone()),
+        _ => {}
+    }
+    (new, restart_required)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{merge, ConfigWatcher};
+    use crate::{load_config, Config, ConfigMode};
+    use std::{
+        fs,
+        sync::mpsc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    const CONFIG: &str = "mode: server\nserver:\n  name: pc\n  ip: 0.0.0.0\n  port: 48899\n";
+
+    #[test]
+    fn test_merge() {
+        let old: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let new: Config = serde_yaml::from_str(
+            "mode: server\nserver:\n  name: pc\n  ip: 127.0.0.1\n  port: 48900\n  layout:\n    - {from: pc, direction: left, to: laptop}\n",
+        )
+        .unwrap();
+        let (merged, restart_required) = merge(&old, new);
+        assert_eq!(restart_required, vec!["server.ip", "server.port"]);
+        let server = merged.server.unwrap();
+        assert_eq!(
+            (server.ip, server.port),
+            (vec!["0.0.0.0".to_string()], 48899)
+        );
+        assert_eq!(server.layout.len(), 1);
+
+        let new: Config = serde_yaml::from_str(
+            "mode: client\nclient:\n  name: c\n  server_ip: 127.0.0.1\n  server_port: 48899\n  direction: left\n",
+        )
+        .unwrap();
+        let (merged, restart_required) = merge(&old, new);
+        assert_eq!(restart_required, vec!["mode"]);
+        assert_eq!(merged.mode, ConfigMode::Server);
+        assert!(merged.server.is_some());
+    }
+
+    #[test]
+    fn test_watcher() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = std::env::temp_dir().join(format!("minput-reload-{}.yaml", nanos));
+        fs::write(&path, CONFIG).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let file = path.clone();
+        let mut watcher =
+            ConfigWatcher::start(vec![path.clone()], Duration::from_millis(20), move || {
+                tx.send(load_config(&file).map(|c| c.server.unwrap().port))
+                    .unwrap();
+            })
+            .unwrap();
+        fs::write(&path, CONFIG.replace("48899", "48900")).unwrap();
+        let port = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(port.unwrap(), 48900);
+
+        fs::write(&path, "mode: [").unwrap();
+        let result = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(result.is_err());
+
+        watcher.stop();
+        fs::remove_file(&path).unwrap();
+    }
+}
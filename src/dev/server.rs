@@ -1,56 +1,1279 @@
-use crate::{net::server, net::server::ACTIVE_CLIENT, CONFIG};
-use anyhow::Result;
-use log::info;
-use log::warn;
-use rdev::listen;
-use rdev::Event;
-use rdev::EventType;
+#[cfg(unix)]
+use crate::control::ControlServer;
+use crate::{
+    check_config,
+    control::{ClientStatus, ControlRequest, ControlResponse},
+    dev::{
+        edge::{EdgeSwitcher, LockKey, Motion},
+        hotkey::{HotkeyEvent, Hotkeys},
+        keymap::Keymap,
+        reload::{self, ConfigWatcher, RELOAD_INTERVAL},
+    },
+    layout::Layout,
+    metrics::{Direction, Metrics, MetricsServer},
+    net::{
+        discovery::{fingerprint, load_id, DiscoveryResponder, SERVER_ID_FILE},
+        protocol::{
+            event_text, Announce, Event as ProtocolEvent, Flag, KeyMouse, Protocol, ServerInfo,
+        },
+        server::{Client, ClientSender, ServerState, UdpServer},
+    },
+    sources::{user_dir, ConfigSources},
+    status::{Notifier, Status},
+    Config, ConfigHotkey, ConfigHotkeyAction, ConfigKeyboardMode, ConfigMouseMode, ConfigServer,
+    ConfigServerClient, Context,
+};
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use rdev::{listen, simulate, Event, EventType};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-pub fn start() -> Result<()> {
-    //TODO 需要检测鼠标键盘是否存在，如果不存在则进行警告
-    let server_config = CONFIG.server.as_ref().expect("配置文件错误");
-    let (tx, rx) = std::sync::mpsc::channel::<Event>();
+/// 检查边缘停留时间的间隔
+const TICK_INTERVAL: Duration = Duration::from_millis(20);
 
-    server::start(server_config.ip.as_str(), server_config.port, rx)?;
+/// 服务端实例，持有配置、屏幕和已连接的客户端
+pub struct Server {
+    current: Arc<Current>,
+    state: Arc<ServerState>,
+    watcher: Option<ConfigWatcher>,
+    udp: Option<UdpServer>,
+    discovery: Option<DiscoveryResponder>,
+    #[cfg(unix)]
+    control: Option<ControlServer>,
+    metrics: Option<MetricsServer>,
+    /// 配置来源，控制接口重新加载时使用
+    sources: Option<ConfigSources>,
+    /// 鼠标键盘事件处理器，由监听线程和控制接口共享，启动时创建
+    input: Option<Arc<Mutex<Input>>>,
+    /// 停止标记，每次启动重新创建，停止后的事件处理器不再处理事件
+    stop: Arc<AtomicBool>,
+    /// 定时检查边缘停留时间的线程
+    ticker: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// 检查配置并创建服务端，不绑定端口
+    pub fn new(config: Config) -> Result<Self> {
+        let context = Context::new(config)?;
+        context
+            .config
+            .server
+            .as_ref()
+            .ok_or_else(|| anyhow!("缺少server配置"))?;
+        info!("config info: {:#?}", context.config);
+        info!("display info: {:#?}", context.display);
+        info!("keyboard layout: {}", context.keyboard_layout.name);
+        Ok(Server {
+            current: Arc::new(Current::new(context)),
+            state: Arc::new(ServerState::default()),
+            watcher: None,
+            udp: None,
+            discovery: None,
+            #[cfg(unix)]
+            control: None,
+            metrics: None,
+            sources: None,
+            input: None,
+            stop: Arc::new(AtomicBool::new(true)),
+            ticker: None,
+        })
+    }
+
+    /// 当前生效的配置
+    pub fn config(&self) -> Config {
+        self.current.get().config.clone()
+    }
+
+    /// 绑定端口并启动网络线程，不监听鼠标键盘
+    pub fn start(&mut self) -> Result<()> {
+        if self.udp.is_some() {
+            return Err(anyhow!("服务已经启动"));
+        }
+        let context = self.current.get();
+        let config = server_config(&context);
+        let info = ServerInfo {
+            name: config.name.clone(),
+            keyboard_layout: context.keyboard_layout.name.clone(),
+        };
+        let (tx, rx) = mpsc::channel::<Protocol>();
+        let udp = UdpServer::start(&config.ip, config.port, info, self.state.clone(), rx)?;
+        let addr = udp.local_addr()?;
+        info!("start server success: {:?}", udp.local_addrs());
+        if config.discovery {
+            //发现端口被占用时不影响服务，客户端仍可以通过配置的地址连接
+            let announce = Announce {
+                name: config.name.clone(),
+                port: addr.port(),
+                fingerprint: fingerprint(&config.name, &server_id()),
+            };
+            match DiscoveryResponder::start(&udp.local_addrs(), config.discovery_port, announce) {
+                Ok(discovery) => {
+                    if let Ok(addr) = discovery.local_addr() {
+                        info!("answer discovery on {}", addr);
+                    }
+                    self.discovery = Some(discovery);
+                }
+                Err(e) => warn!("绑定发现端口{}失败: {}", config.discovery_port, e),
+            }
+        }
+        if let Some(port) = context.config.metrics_port {
+            let metrics = MetricsServer::start(port, self.state.metrics.clone())?;
+            info!("metrics on http://{}/metrics", metrics.local_addr());
+            self.metrics = Some(metrics);
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let input = Arc::new(Mutex::new(self.input(tx, udp.sender(), stop.clone())?));
+        #[cfg(unix)]
+        if let Some(path) = &config.control_socket {
+            let control = Control {
+                current: self.current.clone(),
+                state: self.state.clone(),
+                sources: self.sources.clone(),
+                input: input.clone(),
+                sender: udp.sender(),
+            };
+            self.control = Some(ControlServer::start(path, move |request| {
+                control.handle(request)
+            })?);
+        }
+        let ticker = {
+            let (input, stop) = (input.clone(), stop.clone());
+            thread::Builder::new()
+                .name("minput-tick".to_string())
+                .spawn(move || tick(&input, &stop))?
+        };
+        self.udp = Some(udp);
+        self.input = Some(input);
+        self.stop = stop;
+        self.ticker = Some(ticker);
+        self.state.notifier.notify(Status::Started { addr });
+        Ok(())
+    }
+
+    /// 设置状态回调
+    pub fn on_status<F>(&self, f: F)
+    where
+        F: Fn(&Status) + Send + Sync + 'static,
+    {
+        self.state.notifier.set(f);
+    }
+
+    /// 在后台线程监听鼠标键盘，需要先启动服务
+    ///
+    /// rdev的监听无法中止，停止服务后监听线程继续存在，但不再处理事件。
+    pub fn capture(&self) -> Result<()> {
+        let input = self.input.clone().ok_or_else(|| anyhow!("服务未启动"))?;
+        thread::Builder::new()
+            .name("minput-listen".to_string())
+            .spawn(move || {
+                if let Err(e) = listen(move |event| handle_event(&input, event)) {
+                    error!("监听鼠标键盘失败: {:?}", e);
+                }
+            })?;
+        Ok(())
+    }
+
+    /// 启动服务并监听鼠标键盘，阻塞直到监听失败
+    pub fn run(&mut self) -> Result<()> {
+        //TODO 需要检测鼠标键盘是否存在，如果不存在则进行警告
+        self.start()?;
+        let input = self.input.clone().ok_or_else(|| anyhow!("服务未启动"))?;
+        let result = listen(move |event| handle_event(&input, event))
+            .map_err(|e| anyhow!("监听鼠标键盘失败: {:?}", e));
+        self.stop();
+        result
+    }
+
+    /// 监视配置文件，任一来源的文件修改后重新合并并加载
+    pub fn watch(&mut self, sources: ConfigSources) -> Result<()> {
+        let (current, notifier) = (self.current.clone(), self.state.notifier.clone());
+        self.sources = Some(sources.clone());
+        let files = sources.files();
+        info!("watch config files {:?}", files);
+        let watcher = ConfigWatcher::start(files, RELOAD_INTERVAL, move || {
+            let result = sources
+                .load()
+                .and_then(|layered| reload_config(&current, layered.config));
+            notify_reload(&notifier, result).ok();
+        })?;
+        self.watcher = Some(watcher);
+        Ok(())
+    }
+
+    /// 重新加载配置，返回需要重启才能生效的字段
+    ///
+    /// 布局、热键、按键重映射、边缘切换条件等立即生效；监听地址、服务器名字、
+    /// 显示器和键盘布局保留原值。新配置无效时继续使用原配置。
+    pub fn reload(&self, config: Config) -> Result<Vec<String>> {
+        let result = reload_config(&self.current, config);
+        notify_reload(&self.state.notifier, result)
+    }
+
+    /// 停止网络线程并关闭端口，已连接的客户端保留到下次启动
+    pub fn stop(&mut self) {
+        if let Some(mut watcher) = self.watcher.take() {
+            watcher.stop();
+        }
+        self.stop.store(true, Ordering::Relaxed);
+        self.input = None;
+        if let Some(ticker) = self.ticker.take() {
+            if ticker.join().is_err() {
+                error!("tick thread panicked");
+            }
+        }
+        #[cfg(unix)]
+        if let Some(mut control) = self.control.take() {
+            control.stop();
+        }
+        if let Some(mut metrics) = self.metrics.take() {
+            metrics.stop();
+        }
+        if let Some(mut discovery) = self.discovery.take() {
+            discovery.stop();
+        }
+        if let Some(mut udp) = self.udp.take() {
+            udp.stop();
+            info!("server stopped");
+            self.state.notifier.notify(Status::Stopped);
+        }
+    }
+
+    /// 实际绑定的地址，未启动时为None
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.udp.as_ref().and_then(|udp| udp.local_addr().ok())
+    }
+
+    /// Prometheus文本格式的运行指标
+    pub fn metrics(&self) -> String {
+        self.state.metrics.render()
+    }
+
+    /// 已连接的客户端名称
+    pub fn clients(&self) -> Vec<String> {
+        self.state
+            .clients
+            .read()
+            .map(|clients| clients.iter().map(|c| c.info.name.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// 创建鼠标键盘事件处理器，`tx`中的报文发送给当前激活的客户端
+    fn input(
+        &self,
+        tx: Sender<Protocol>,
+        sender: ClientSender,
+        stop: Arc<AtomicBool>,
+    ) -> Result<Input> {
+        let context = self.current.get();
+        let config = server_config(&context);
+        let (keymaps, hotkeys) = rules(config)?;
+        let layout = Layout::new(&config.layout);
+        let mut switcher = EdgeSwitcher::new(layout, &config.name, context.display.clone());
+        switcher.set_guard(config.edge_guard.clone());
+        Ok(Input {
+            generation: self.current.generation(),
+            current: self.current.clone(),
+            lock_key: LockKey::new(config.lock_key),
+            context,
+            stop,
+            state: self.state.clone(),
+            tx,
+            sender,
+            keymaps,
+            hotkeys,
+            switcher,
+            buttons: vec![],
+            pressed: vec![],
+            relative_toggled: false,
+            typed: vec![],
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 当前生效的配置，重新加载时整体替换
+#[derive(Debug)]
+struct Current {
+    context: RwLock<Arc<Context>>,
+    /// 每次替换加一，事件处理器据此判断是否需要重建
+    generation: AtomicU64,
+}
+
+impl Current {
+    fn new(context: Context) -> Self {
+        Current {
+            context: RwLock::new(Arc::new(context)),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self) -> Arc<Context> {
+        match self.context.read() {
+            Ok(context) => context.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    fn replace(&self, context: Context) {
+        match self.context.write() {
+            Ok(mut current) => *current = Arc::new(context),
+            Err(e) => *e.into_inner() = Arc::new(context),
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// 服务端配置，创建Server时已检查
+fn server_config(context: &Context) -> &ConfigServer {
+    context.config.server.as_ref().expect("缺少server配置")
+}
+
+/// 本机保存的服务器标识，用于计算指纹，无法读写时指纹只按名字计算
+fn server_id() -> String {
+    let Some(dir) = user_dir() else {
+        warn!("找不到用户配置目录，服务器指纹只按名字计算");
+        return String::new();
+    };
+    load_id(&dir.join(SERVER_ID_FILE)).unwrap_or_else(|e| {
+        warn!("{}，服务器指纹只按名字计算", e);
+        String::new()
+    })
+}
+
+/// 按配置创建按键重映射和热键
+fn rules(config: &ConfigServer) -> Result<(HashMap<String, Keymap>, Hotkeys)> {
+    //按客户端配置的按键重映射
+    let mut keymaps = HashMap::new();
+    for (name, client) in &config.clients {
+        let keymap = Keymap::new(&client.keymap).map_err(|e| anyhow!("clients.{}.{}", name, e))?;
+        if !keymap.is_empty() {
+            keymaps.insert(name.clone(), keymap);
+        }
+    }
+
+    //relative_mouse_hotkey作为切换相对移动模式的热键
+    let mut hotkeys = config.hotkeys.clone();
+    if !config.relative_mouse_hotkey.is_empty() {
+        hotkeys.push(ConfigHotkey {
+            keys: config.relative_mouse_hotkey.clone(),
+            action: ConfigHotkeyAction::Relative,
+        });
+    }
+    Ok((keymaps, Hotkeys::new(hotkeys)))
+}
+
+/// 检查新配置，保留需要重启才能生效的字段后替换当前配置
+fn reload_config(current: &Current, config: Config) -> Result<Vec<String>> {
+    check_config(&config)?;
+    let old = current.get();
+    let (config, restart_required) = reload::merge(&old.config, config);
+    //保留原服务器名字后布局可能不再有效
+    check_config(&config)?;
+    current.replace(Context {
+        config,
+        display: old.display.clone(),
+        keyboard_layout: old.keyboard_layout.clone(),
+    });
+    Ok(restart_required)
+}
+
+/// 记录并通知重新加载的结果
+fn notify_reload(notifier: &Notifier, result: Result<Vec<String>>) -> Result<Vec<String>> {
+    match &result {
+        Ok(restart_required) => {
+            info!("config reloaded");
+            for field in restart_required {
+                warn!("{}修改后需要重启服务才能生效", field);
+            }
+            notifier.notify(Status::Reloaded {
+                restart_required: restart_required.clone(),
+            });
+        }
+        Err(e) => {
+            error!("重新加载配置失败，继续使用原配置: {}", e);
+            notifier.notify(Status::ReloadFailed {
+                error: e.to_string(),
+            });
+        }
+    }
+    result
+}
+
+/// 监听线程中处理鼠标键盘事件
+fn handle_event(input: &Mutex<Input>, event: Event) {
+    match input.lock() {
+        Ok(mut input) => input.handle(event),
+        Err(e) => error!("input lock error: {}", e),
+    }
+}
+
+/// 执行控制接口的请求
+struct Control {
+    current: Arc<Current>,
+    state: Arc<ServerState>,
+    sources: Option<ConfigSources>,
+    input: Arc<Mutex<Input>>,
+    sender: ClientSender,
+}
+
+impl Control {
+    fn handle(&self, request: ControlRequest) -> ControlResponse {
+        let server = self.current.get().config.server.clone();
+        let server_name = server.map(|s| s.name).unwrap_or_default();
+        match request {
+            ControlRequest::Clients => ControlResponse::data(self.clients()),
+            ControlRequest::Active => {
+                let screen = self
+                    .clients()
+                    .into_iter()
+                    .find(|c| c.active)
+                    .map_or(server_name, |c| c.name);
+                ControlResponse::data(serde_json::json!({ "screen": screen }))
+            }
+            ControlRequest::Switch { screen } => {
+                if screen != server_name && !self.clients().iter().any(|c| c.name == screen) {
+                    return ControlResponse::error(format!("屏幕{}未连接", screen));
+                }
+                let action = ConfigHotkeyAction::Switch {
+                    screen: screen.clone(),
+                };
+                //切换完成后才回复
+                match self.apply(&action) {
+                    Some(current) if current == screen => ControlResponse::ok(),
+                    _ => ControlResponse::error(format!("切换到屏幕{}失败", screen)),
+                }
+            }
+            ControlRequest::Kick { name } => {
+                let client = self.clients().into_iter().find(|c| c.name == name);
+                let client = match client {
+                    Some(client) => client,
+                    None => return ControlResponse::error(format!("客户端{}未连接", name)),
+                };
+                //断开当前激活的客户端前回到服务器屏幕
+                if client.active {
+                    self.apply(&ConfigHotkeyAction::Server);
+                }
+                if let Err(e) = self.sender.disconnect(client.addr) {
+                    warn!("disconnect client {} error: {}", name, e);
+                }
+                self.state.remove_client(&name);
+                ControlResponse::ok()
+            }
+            ControlRequest::Lock | ControlRequest::Unlock => {
+                let locked = request == ControlRequest::Lock;
+                self.state.cursor_locked.store(locked, Ordering::Relaxed);
+                info!("cursor locked: {}", locked);
+                ControlResponse::ok()
+            }
+            ControlRequest::Reload => {
+                let sources = match &self.sources {
+                    Some(sources) => sources,
+                    None => return ControlResponse::error("服务端未指定配置文件"),
+                };
+                let result = sources
+                    .load()
+                    .and_then(|layered| reload_config(&self.current, layered.config));
+                match notify_reload(&self.state.notifier, result) {
+                    Ok(restart_required) => ControlResponse::data(
+                        serde_json::json!({ "restart_required": restart_required }),
+                    ),
+                    Err(e) => ControlResponse::error(e),
+                }
+            }
+            ControlRequest::Metrics => ControlResponse::data(self.state.metrics.render()),
+        }
+    }
+
+    /// 立即执行动作，返回执行后光标所在的屏幕
+    fn apply(&self, action: &ConfigHotkeyAction) -> Option<String> {
+        match self.input.lock() {
+            Ok(mut input) => {
+                input.apply(action);
+                Some(input.switcher.screen().to_string())
+            }
+            Err(e) => {
+                error!("input lock error: {}", e);
+                None
+            }
+        }
+    }
+
+    fn clients(&self) -> Vec<ClientStatus> {
+        let active = self.state.active_client.read().ok().and_then(|a| *a);
+        with_clients(&self.state, |clients| {
+            clients
+                .iter()
+                .map(|c| ClientStatus {
+                    name: c.info.name.clone(),
+                    addr: c.addr,
+                    active: Some(c.addr) == active,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+}
+
+/// 服务器鼠标键盘事件处理
+struct Input {
+    current: Arc<Current>,
+    /// 事件处理器使用的配置版本
+    generation: u64,
+    context: Arc<Context>,
+    stop: Arc<AtomicBool>,
+    state: Arc<ServerState>,
+    tx: Sender<Protocol>,
+    /// 切换屏幕时向离开的客户端发送释放事件
+    sender: ClientSender,
+    keymaps: HashMap<String, Keymap>,
+    hotkeys: Hotkeys,
+    switcher: EdgeSwitcher,
+    /// 按住的鼠标按钮，按住时按配置禁止切换屏幕
+    buttons: Vec<KeyMouse>,
+    /// 已按下的按键，用于检测组合键
+    pressed: Vec<KeyMouse>,
+    /// 通过热键切换相对移动模式
+    relative_toggled: bool,
+    /// 锁定键打开期间光标锁定在当前屏幕
+    lock_key: LockKey,
+    /// 按下时以文本发送的按键，释放时不再发送
+    typed: Vec<KeyMouse>,
+}
+
+impl Input {
+    fn handle(&mut self, event: Event) {
+        if self.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        //按住按键或按钮时不替换规则，防止按键重映射的状态丢失
+        if self.pressed.is_empty() && self.buttons.is_empty() {
+            self.refresh();
+        }
+        let key = match event.event_type {
+            EventType::KeyPress(key) => Some((KeyMouse::from(key), true)),
+            EventType::KeyRelease(key) => Some((KeyMouse::from(key), false)),
+            _ => None,
+        };
+        match event.event_type {
+            EventType::ButtonPress(button) => {
+                self.buttons.push(KeyMouse::from(button));
+                self.switcher.set_button_held(true);
+            }
+            EventType::ButtonRelease(button) => {
+                self.buttons.retain(|b| *b != KeyMouse::from(button));
+                self.switcher.set_button_held(!self.buttons.is_empty());
+            }
+            _ => {}
+        }
+        if let Some((key, press)) = key {
+            //锁定键只在服务器上生效，不发送给客户端
+            if self.lock_key.on_key(key, press) {
+                return;
+            }
+            if !press {
+                self.pressed.retain(|k| *k != key);
+            } else if !self.pressed.contains(&key) {
+                self.pressed.push(key);
+            }
+            match self.hotkeys.on_key(key, press) {
+                HotkeyEvent::Forward => {}
+                HotkeyEvent::Consumed => return,
+                HotkeyEvent::Triggered { action, release } => {
+                    //释放触发热键前已发送给当前屏幕的按键，防止客户端按键卡住
+                    if !self.switcher.is_local() {
+                        for key in release {
+                            let protocol =
+                                Protocol::new(Flag::KeyMouse, key, ProtocolEvent::Release);
+                            self.send(protocol);
+                        }
+                    }
+                    if let Some(protocol) = self.run_hotkey(&action) {
+                        self.send(protocol);
+                    }
+                    return;
+                }
+            }
+        }
+
+        let protocol = match event.event_type {
+            EventType::MouseMove { x, y } => {
+                self.update_locked();
+                match self.active_client(x, y) {
+                    Some(protocol) => protocol,
+                    None => return,
+                }
+            }
+            //光标在服务器屏幕上时不转发
+            _ if self.switcher.is_local() => return,
+            EventType::KeyPress(key) if self.is_text_mode() => {
+                match typed_text(&event, &self.pressed) {
+                    Some(text) => {
+                        self.typed.push(KeyMouse::from(key));
+                        Protocol::text(text)
+                    }
+                    None => event.into(),
+                }
+            }
+            EventType::KeyRelease(key) if self.typed.contains(&KeyMouse::from(key)) => {
+                self.typed.retain(|k| *k != KeyMouse::from(key));
+                return;
+            }
+            _ => event.into(),
+        };
+        self.send(protocol);
+    }
+
+    /// 定时检查光标在边缘的停留时间，光标停止移动后也能按停留时间切换屏幕
+    fn tick(&mut self) {
+        if self.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        self.update_locked();
+        let switcher = &mut self.switcher;
+        let now = Instant::now();
+        let motion = with_clients(&self.state, |clients| switcher.tick(clients, now)).flatten();
+        if let Some(protocol) = motion.and_then(|motion| self.apply_motion(motion)) {
+            self.send(protocol);
+        }
+    }
+
+    /// 按锁定热键和锁定键更新是否禁止边缘切换
+    fn update_locked(&mut self) {
+        let locked = self.state.cursor_locked.load(Ordering::Relaxed) || self.lock_key.is_on();
+        if locked != self.switcher.is_locked() {
+            info!("edge switching locked: {}", locked);
+            self.switcher.set_locked(locked);
+        }
+    }
+
+    /// 执行控制接口请求的动作
+    fn apply(&mut self, action: &ConfigHotkeyAction) {
+        if self.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        if self.pressed.is_empty() && self.buttons.is_empty() {
+            self.refresh();
+        }
+        if let Some(protocol) = self.run_hotkey(action) {
+            self.send(protocol);
+        }
+    }
+
+    /// 配置重新加载后重建布局、热键和按键重映射
+    fn refresh(&mut self) {
+        let generation = self.current.generation();
+        if generation == self.generation {
+            return;
+        }
+        self.generation = generation;
+        let context = self.current.get();
+        let config = server_config(&context);
+        match rules(config) {
+            Ok((keymaps, hotkeys)) => {
+                self.keymaps = keymaps;
+                self.hotkeys = hotkeys;
+            }
+            Err(e) => error!("apply reloaded config error: {}", e),
+        }
+        self.switcher.set_layout(Layout::new(&config.layout));
+        self.switcher.set_guard(config.edge_guard.clone());
+        if config.lock_key != server_config(&self.context).lock_key {
+            self.lock_key = LockKey::new(config.lock_key);
+        }
+        self.context = context;
+        info!("reloaded config applied");
+    }
+
+    fn send(&mut self, protocol: Protocol) {
+        send(
+            &self.tx,
+            &mut self.keymaps,
+            self.switcher.screen(),
+            protocol,
+            &self.state.metrics,
+        );
+    }
+
+    /// 执行热键动作，返回需要发送给客户端的光标移动
+    fn run_hotkey(&mut self, action: &ConfigHotkeyAction) -> Option<Protocol> {
+        info!("hotkey: {:?}", action);
+        let switcher = &mut self.switcher;
+        let motion = match action {
+            ConfigHotkeyAction::Switch { screen } => {
+                match with_clients(&self.state, |clients| switcher.jump(screen, clients))? {
+                    Some(motion) => motion,
+                    None => {
+                        warn!("screen {} is not connected", screen);
+                        return None;
+                    }
+                }
+            }
+            ConfigHotkeyAction::Cycle => {
+                with_clients(&self.state, |clients| switcher.cycle(clients))?
+            }
+            ConfigHotkeyAction::Server => switcher.home(),
+            ConfigHotkeyAction::Lock => {
+                let locked = !self.state.cursor_locked.fetch_xor(true, Ordering::Relaxed);
+                info!("cursor locked: {}", locked);
+                return None;
+            }
+            ConfigHotkeyAction::Relative => {
+                self.relative_toggled = !self.relative_toggled;
+                info!("relative mouse mode toggled: {}", self.relative_toggled);
+                return None;
+            }
+        };
+        self.apply_motion(motion)
+    }
+
+    /// 计算光标所在屏幕并切换激活的客户端，返回需要发送给客户端的光标移动
+    fn active_client(&mut self, x: f64, y: f64) -> Option<Protocol> {
+        let switcher = &mut self.switcher;
+        let motion = with_clients(&self.state, |clients| switcher.on_move(x, y, clients))?;
+        self.apply_motion(motion)
+    }
 
-    let handle_event = move |event: Event| {
-        //判断是否有另一个屏幕在激活状态
-        if let Ok(client) = ACTIVE_CLIENT.read() {
-            //如果另一个屏幕存在激活状态，则不在进行计算鼠标是否在当前屏幕。
-            //脱离另外一个屏幕，由另外一个屏幕判断是否在当前屏幕，并告诉主服务端。
-            if client.is_some() {
-                //return;
+    /// 按光标移动结果切换激活的客户端、复位服务器光标
+    fn apply_motion(&mut self, motion: Motion) -> Option<Protocol> {
+        let event = match motion {
+            Motion::Local => None,
+            Motion::Remote { x, y, dx, dy } => {
+                if self.is_relative() {
+                    //相对移动模式下服务器光标保持在屏幕中心，复位产生的移动事件偏移量为0，不再复位
+                    if dx == 0.0 && dy == 0.0 {
+                        None
+                    } else {
+                        self.switcher.recenter();
+                        Some(ProtocolEvent::RelativeMove(dx, dy))
+                    }
+                } else {
+                    Some(ProtocolEvent::Move(x, y))
+                }
+            }
+            Motion::Switch { screen, x, y } => {
+                info!("switch to screen {}", screen);
+                let addr = with_clients(&self.state, |clients| {
+                    clients
+                        .iter()
+                        .find(|c| c.info.name == screen)
+                        .map(|c| c.addr)
+                })
+                .flatten();
+                let previous = match self.state.active_client.write() {
+                    Ok(mut client) => std::mem::replace(&mut *client, addr),
+                    Err(_) => None,
+                };
+                self.release_held(previous);
+                self.state.notifier.notify(Status::ScreenChanged { screen });
+                //进入客户端屏幕时总是发送绝对坐标，确定光标的初始位置
+                addr.map(|_| ProtocolEvent::Move(x, y))
+            }
+        };
+        if let Some((x, y)) = self.switcher.take_warp() {
+            if let Err(e) = simulate(&EventType::MouseMove { x, y }) {
+                warn!("warp cursor error: {:?}", e);
             }
         }
+        event.map(|event| Protocol::new(Flag::KeyMouse, KeyMouse::MouseMove, event))
+    }
 
-        if let EventType::MouseMove { x, y } = event.event_type {
-            //判断鼠标是否在当前屏幕上
-            //如果在当前屏幕上，则发送鼠标移动事件给主服务端
-            //如果不在当前屏幕上，则不发送鼠标移动事件给主服务端
+    /// 切换屏幕时重置按键重映射，在离开的客户端上释放按住的按键和鼠标按钮
+    ///
+    /// 包括按规则重映射后发送的按键、其余按住的按键（如跨越边缘时按住的Shift）和拖动时按住的按钮。
+    /// 之后的释放事件发送给新的屏幕，离开的客户端收不到，因此直接发送给`previous`。
+    fn release_held(&mut self, previous: Option<SocketAddr>) {
+        let mut keys: Vec<KeyMouse> = vec![];
+        for keymap in self.keymaps.values_mut() {
+            keys.extend(keymap.reset());
         }
+        let Some(addr) = previous else {
+            return;
+        };
+        //以文本发送的按键没有发送按下事件
+        let held = self
+            .pressed
+            .iter()
+            .rev()
+            .filter(|key| !self.typed.contains(key))
+            .chain(self.buttons.iter().rev());
+        for key in held {
+            if !keys.contains(key) {
+                keys.push(*key);
+            }
+        }
+        for key in keys {
+            let protocol = Protocol::new(Flag::KeyMouse, key, ProtocolEvent::Release);
+            if let Err(e) = self.sender.send(addr, &protocol) {
+                warn!("release {:?} on {} error: {}", key, addr, e);
+            }
+        }
+    }
+
+    /// 当前屏幕客户端的配置
+    fn client_config(&self) -> Option<&ConfigServerClient> {
+        self.context
+            .config
+            .server
+            .as_ref()
+            .and_then(|server| server.clients.get(self.switcher.screen()))
+    }
+
+    /// 当前客户端是否使用文本输入模式
+    fn is_text_mode(&self) -> bool {
+        self.client_config()
+            .map(|client| client.keyboard_mode == ConfigKeyboardMode::Text)
+            .unwrap_or_default()
+    }
+
+    /// 当前客户端是否使用相对移动模式，组合键切换时与客户端配置相反
+    fn is_relative(&self) -> bool {
+        let mode = self
+            .client_config()
+            .map(|client| client.mouse_mode)
+            .unwrap_or_default();
+        (mode == ConfigMouseMode::Relative) != self.relative_toggled
+    }
+}
 
-        tx.send(event)
-            .unwrap_or_else(|e| warn!("send event error: {:?}", e))
+/// 按当前屏幕的按键重映射规则发送报文
+fn send(
+    tx: &Sender<Protocol>,
+    keymaps: &mut HashMap<String, Keymap>,
+    screen: &str,
+    protocol: Protocol,
+    metrics: &Metrics,
+) {
+    let protocols = match keymaps.get_mut(screen) {
+        Some(keymap) if protocol.flag == Flag::KeyMouse && protocol.raw.is_none() => {
+            match protocol.event {
+                ProtocolEvent::Press | ProtocolEvent::Release => keymap
+                    .map(protocol.key_mouse, protocol.event == ProtocolEvent::Press)
+                    .into_iter()
+                    .map(|(key, press)| {
+                        let event = if press {
+                            ProtocolEvent::Press
+                        } else {
+                            ProtocolEvent::Release
+                        };
+                        Protocol::new(Flag::KeyMouse, key, event)
+                    })
+                    .collect(),
+                _ => vec![protocol],
+            }
+        }
+        _ => vec![protocol],
     };
+    for protocol in protocols {
+        if let Err(e) = tx.send(protocol) {
+            warn!("send event error: {:?}", e);
+            metrics.count(Direction::Dropped, &e.0);
+        }
+    }
+}
 
-    info!("start server success");
-    if let Err(error) = listen(handle_event) {
-        panic!("监听鼠标键盘失败: {:?}", error);
+/// 每隔[`TICK_INTERVAL`]检查一次边缘停留时间，直到`stop`置位
+fn tick(input: &Mutex<Input>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(TICK_INTERVAL);
+        match input.lock() {
+            Ok(mut input) => input.tick(),
+            Err(e) => {
+                error!("input lock error: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// 读取已连接的客户端
+fn with_clients<T, F>(state: &ServerState, f: F) -> Option<T>
+where
+    F: FnOnce(&[Client]) -> T,
+{
+    match state.clients.read() {
+        Ok(clients) => Some(f(&clients)),
+        Err(e) => {
+            error!("clients read error: {}", e);
+            None
+        }
     }
+}
 
-    Ok(())
+/// 按键产生的文本，按下Ctrl、Alt、Meta时为快捷键，仍然发送按键
+fn typed_text<'a>(event: &'a Event, pressed: &[KeyMouse]) -> Option<&'a str> {
+    let shortcut = pressed.iter().any(|k| {
+        matches!(
+            k,
+            KeyMouse::ControlLeft
+                | KeyMouse::ControlRight
+                | KeyMouse::Alt
+                | KeyMouse::MetaLeft
+                | KeyMouse::MetaRight
+        )
+    });
+    if shortcut {
+        return None;
+    }
+    event_text(event)
 }
 
-pub fn active_client(x: f64, y: f64) {
-    if let Ok(clients) = CLIENTS.read() {
-        //判断是否有客户端接入
-        if clients.len() > 0 {
-            //如果有客户端接入则计算鼠标是否在当前屏幕
-            if x as u64 >= DISPLAY.width - 10 {
-                if let Ok(mut client) = ACTIVE_CLIENT.write() {
-                    *client = Some(clients[0]);
+#[cfg(test)]
+mod test {
+    use super::Server;
+    use crate::{net::free_port, Client, Config};
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn server_config(name: &str, port: u16) -> Config {
+        serde_yaml::from_str(&format!(
+            "mode: server\nserver:\n  name: {}\n  ip: 127.0.0.1\n  port: {}\ndisplay:\n  monitors:\n    - {{x: 0, y: 0, width: 1920, height: 1080}}\n",
+            name, port
+        ))
+        .unwrap()
+    }
+
+    fn client_config(name: &str, port: u16) -> Config {
+        serde_yaml::from_str(&format!(
+            "mode: client\nclient:\n  name: {}\n  server_ip: 127.0.0.1\n  server_port: {}\n  direction: right\ndisplay:\n  monitors:\n    - {{x: 0, y: 0, width: 1280, height: 720}}\n",
+            name, port
+        ))
+        .unwrap()
+    }
+
+    /// 等待条件满足，超时返回false
+    fn wait(f: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if f() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_multiple_instances() {
+        let mut servers = vec![];
+        let mut clients = vec![];
+        for (server, client) in [("s1", "c1"), ("s2", "c2")] {
+            let mut s = Server::new(server_config(server, free_port())).unwrap();
+            s.start().unwrap();
+            let port = s.local_addr().unwrap().port();
+            let mut c = Client::new(client_config(client, port)).unwrap();
+            c.start().unwrap();
+            servers.push(s);
+            clients.push(c);
+        }
+        for (server, (client, name)) in servers.iter().zip(clients.iter().zip(["c1", "c2"])) {
+            assert!(wait(|| server.clients() == vec![name.to_string()]));
+            assert!(wait(|| client.server_name().is_some()));
+        }
+        assert_eq!(clients[0].server_name().as_deref(), Some("s1"));
+        assert_eq!(clients[1].server_name().as_deref(), Some("s2"));
+
+        for server in &mut servers {
+            server.stop();
+            assert!(server.local_addr().is_none());
+        }
+        for client in &mut clients {
+            client.stop().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reconnect() {
+        //服务端启动前客户端重复发送连接请求
+        let port = free_port();
+        let mut client = Client::new(client_config("c", port)).unwrap();
+        client.start().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(client.server_name().is_none());
+        let mut server = Server::new(server_config("s1", port)).unwrap();
+        server.start().unwrap();
+        assert!(wait(|| client.server_name().as_deref() == Some("s1")));
+
+        //服务端重启后重新连接
+        server.stop();
+        thread::sleep(Duration::from_millis(500));
+        let mut server = Server::new(server_config("s2", port)).unwrap();
+        server.start().unwrap();
+        assert!(wait(|| client.server_name().as_deref() == Some("s2")));
+        assert_eq!(server.clients(), vec!["c"]);
+
+        client.stop().unwrap();
+        server.stop();
+    }
+
+    #[test]
+    fn test_release_on_switch() {
+        use crate::{
+            net::{
+                client::UdpClient,
+                protocol::{ClientInfo, Event as ProtocolEvent, Flag, KeyMouse, Protocol},
+            },
+            ConfigClientDirection, ConfigHotkeyAction, Display,
+        };
+        use rdev::{Event, EventType, Key};
+        use std::time::SystemTime;
+
+        let mut server = Server::new(server_config("s", free_port())).unwrap();
+        server.start().unwrap();
+        let udp = UdpClient::connect(server.local_addr().unwrap()).unwrap();
+        udp.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let info = ClientInfo {
+            name: "c".to_string(),
+            display: Display::new(1280, 720),
+            direction: ConfigClientDirection::Right,
+            keyboard_layout: "us".to_string(),
+        };
+        udp.send(Protocol::client_init(&info).unwrap()).unwrap();
+        assert!(wait(|| server.clients() == vec!["c"]));
+
+        //在客户端屏幕上按住Shift后回到服务器屏幕
+        let input = server.input.clone().unwrap();
+        let mut input = input.lock().unwrap();
+        input.apply(&ConfigHotkeyAction::Switch {
+            screen: "c".to_string(),
+        });
+        input.handle(Event {
+            time: SystemTime::now(),
+            name: None,
+            event_type: EventType::KeyPress(Key::ShiftLeft),
+        });
+        input.apply(&ConfigHotkeyAction::Server);
+        drop(input);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = vec![];
+        while Instant::now() < deadline {
+            match udp.recv().unwrap() {
+                Some(protocol) if protocol.flag == Flag::KeyMouse => {
+                    let release = protocol.event == ProtocolEvent::Release;
+                    events.push((protocol.key_mouse, protocol.event));
+                    if release {
+                        break;
+                    }
                 }
+                _ => {}
             }
         }
+        assert!(events.contains(&(KeyMouse::ShiftLeft, ProtocolEvent::Press)));
+        assert_eq!(
+            events.last(),
+            Some(&(KeyMouse::ShiftLeft, ProtocolEvent::Release))
+        );
+        server.stop();
+    }
+
+    #[test]
+    fn test_client_stop() {
+        let mut server = Server::new(server_config("s", free_port())).unwrap();
+        server.start().unwrap();
+        let port = server.local_addr().unwrap().port();
+        let mut client = Client::new(client_config("c", port)).unwrap();
+        client.start().unwrap();
+        assert!(wait(|| server.clients() == vec!["c"]));
+
+        //客户端停止时通知服务端，不等待心跳超时
+        client.stop().unwrap();
+        thread::sleep(Duration::from_millis(500));
+        assert!(server.clients().is_empty());
+        server.stop();
+    }
+
+    #[test]
+    fn test_client_timeout() {
+        use crate::{
+            net::{
+                client::UdpClient,
+                protocol::{ClientInfo, Protocol},
+            },
+            status::Status,
+            ConfigClientDirection, ConfigHotkeyAction, Display,
+        };
+        use std::sync::{Arc, Mutex};
+
+        let mut server = Server::new(server_config("s", free_port())).unwrap();
+        let statuses = Arc::new(Mutex::new(vec![]));
+        let recorder = statuses.clone();
+        server.on_status(move |status| recorder.lock().unwrap().push(status.clone()));
+        server.start().unwrap();
+        //只发送连接请求，不回复心跳
+        let udp = UdpClient::connect(server.local_addr().unwrap()).unwrap();
+        let info = ClientInfo {
+            name: "c".to_string(),
+            display: Display::new(1280, 720),
+            direction: ConfigClientDirection::Right,
+            keyboard_layout: "us".to_string(),
+        };
+        udp.send(Protocol::client_init(&info).unwrap()).unwrap();
+        assert!(wait(|| server.clients() == vec!["c"]));
+        let input = server.input.clone().unwrap();
+        input.lock().unwrap().apply(&ConfigHotkeyAction::Switch {
+            screen: "c".to_string(),
+        });
+        assert!(server.state.active_client.read().unwrap().is_some());
+
+        //超时后移除，回到服务器屏幕
+        assert!(wait(|| server.clients().is_empty()));
+        assert!(server.state.active_client.read().unwrap().is_none());
+        assert!(wait(|| input.lock().unwrap().switcher.is_local()));
+        assert!(statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| matches!(s, Status::ClientDisconnected { name, .. } if name == "c")));
+        server.stop();
+    }
+
+    #[test]
+    fn test_dual_stack() {
+        let mut config = server_config("s", free_port());
+        config.server.as_mut().unwrap().ip = vec!["::1".to_string(), "127.0.0.1".to_string()];
+        let mut server = Server::new(config).unwrap();
+        server.start().unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let mut clients = vec![];
+        for (name, ip) in [("c4", "127.0.0.1"), ("c6", "[::1]")] {
+            let mut config = client_config(name, port);
+            config.client.as_mut().unwrap().server_ip = Some(ip.to_string());
+            let mut client = Client::new(config).unwrap();
+            client.start().unwrap();
+            assert!(wait(|| client.server_name().as_deref() == Some("s")));
+            clients.push(client);
+        }
+        let mut names = server.clients();
+        names.sort();
+        assert_eq!(names, vec!["c4", "c6"]);
+
+        for client in &mut clients {
+            client.stop().unwrap();
+        }
+        server.stop();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_control() {
+        use crate::{
+            control::{send_request, ControlRequest},
+            status::Status,
+        };
+        use std::sync::{atomic::Ordering, Arc, Mutex};
+
+        let path = std::env::temp_dir().join(format!("minput-control-{}.sock", free_port()));
+        let mut config = server_config("s", free_port());
+        config.server.as_mut().unwrap().control_socket = Some(path.clone());
+        let mut server = Server::new(config).unwrap();
+        server.start().unwrap();
+        let port = server.local_addr().unwrap().port();
+        let mut client = Client::new(client_config("c", port)).unwrap();
+        let stopped = Arc::new(Mutex::new(false));
+        let recorder = stopped.clone();
+        client.on_status(move |status| {
+            if *status == Status::Stopped {
+                *recorder.lock().unwrap() = true;
+            }
+        });
+        client.start().unwrap();
+        assert!(wait(|| server.clients() == vec!["c"]));
+
+        let request = |request: ControlRequest| send_request(&path, &request).unwrap();
+        let clients = request(ControlRequest::Clients).data.unwrap();
+        assert_eq!(clients[0]["name"], "c");
+        assert_eq!(clients[0]["active"], false);
+        let active = request(ControlRequest::Active).data.unwrap();
+        assert_eq!(active["screen"], "s");
+
+        //双方每秒发送心跳
+        let rtt = "minput_heartbeat_rtt_seconds_count 0";
+        assert!(wait(
+            || !server.metrics().contains(rtt) && !client.metrics().contains(rtt)
+        ));
+        let metrics = request(ControlRequest::Metrics).data.unwrap();
+        assert!(metrics.as_str().unwrap().contains(
+            "minput_events_total{direction=\"received\",flag=\"client_init\",category=\"none\"} 1"
+        ));
+
+        let response = request(ControlRequest::Switch {
+            screen: "x".to_string(),
+        });
+        assert_eq!(response.error.as_deref(), Some("屏幕x未连接"));
+        assert!(
+            request(ControlRequest::Switch {
+                screen: "c".to_string()
+            })
+            .ok
+        );
+        //回复时已经切换
+        let active = request(ControlRequest::Active).data.unwrap();
+        assert_eq!(active["screen"], "c");
+
+        assert!(request(ControlRequest::Lock).ok);
+        assert!(server.state.cursor_locked.load(Ordering::Relaxed));
+        assert!(request(ControlRequest::Unlock).ok);
+        assert!(!server.state.cursor_locked.load(Ordering::Relaxed));
+
+        assert!(
+            request(ControlRequest::Kick {
+                name: "c".to_string()
+            })
+            .ok
+        );
+        assert!(server.clients().is_empty());
+        assert_eq!(request(ControlRequest::Active).data.unwrap()["screen"], "s");
+        //客户端收到断开通知后停止
+        assert!(wait(|| *stopped.lock().unwrap()));
+        //未指定配置文件时不能重新加载
+        assert!(!request(ControlRequest::Reload).ok);
+
+        client.stop().unwrap();
+        server.stop();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_reload() {
+        let server = Server::new(server_config("s", 48899)).unwrap();
+        let mut config = server_config("s", 48900);
+        config.server.as_mut().unwrap().layout = serde_yaml::from_str(
+            "- {from: s, direction: left, to: c1}\n- {from: c1, direction: left, to: c2}\n",
+        )
+        .unwrap();
+        assert_eq!(server.reload(config).unwrap(), vec!["server.port"]);
+        let current = server.config().server.unwrap();
+        assert_eq!(current.port, 48899);
+        assert_eq!(current.layout.len(), 2);
+
+        //无效的配置不替换当前配置
+        let mut config = server_config("s", 48899);
+        config.server.as_mut().unwrap().layout =
+            serde_yaml::from_str("- {from: a, direction: left, to: b}\n").unwrap();
+        assert!(server.reload(config).is_err());
+        assert_eq!(server.config().server.unwrap().layout.len(), 2);
     }
 }
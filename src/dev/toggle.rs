@@ -0,0 +1,80 @@
+//! 读取CapsLock、NumLock、ScrollLock等锁定类按键的切换状态（指示灯）
+
+pub use platform::toggled;
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use crate::net::protocol::KeyMouse;
+    use winapi::um::winuser::{GetKeyState, VK_CAPITAL, VK_NUMLOCK, VK_SCROLL};
+
+    /// 按键的切换状态，不是锁定类按键时返回None
+    pub fn toggled(key: KeyMouse) -> Option<bool> {
+        let vk = match key {
+            KeyMouse::CapsLock => VK_CAPITAL,
+            KeyMouse::NumLock => VK_NUMLOCK,
+            KeyMouse::ScrollLock => VK_SCROLL,
+            _ => return None,
+        };
+        //最低位为切换状态
+        Some(unsafe { GetKeyState(vk) } & 1 != 0)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use crate::net::protocol::KeyMouse;
+    use std::ptr::{null, null_mut};
+    use x11::xlib;
+
+    /// 按键的切换状态，读取同名的键盘指示灯，不是锁定类按键或无法连接X11时返回None
+    pub fn toggled(key: KeyMouse) -> Option<bool> {
+        let name = match key {
+            KeyMouse::CapsLock => c"Caps Lock",
+            KeyMouse::NumLock => c"Num Lock",
+            KeyMouse::ScrollLock => c"Scroll Lock",
+            _ => return None,
+        };
+        unsafe {
+            let display = xlib::XOpenDisplay(null());
+            if display.is_null() {
+                return None;
+            }
+            let atom = xlib::XInternAtom(display, name.as_ptr(), xlib::True);
+            let mut state = 0;
+            let found = atom != 0
+                && xlib::XkbGetNamedIndicator(
+                    display,
+                    atom,
+                    null_mut(),
+                    &mut state,
+                    null_mut(),
+                    null_mut(),
+                ) != 0;
+            xlib::XCloseDisplay(display);
+            found.then_some(state != 0)
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use crate::net::protocol::KeyMouse;
+    use core_graphics::{event::CGEventFlags, event_source::CGEventSourceStateID};
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGEventSourceFlagsState(state: CGEventSourceStateID) -> u64;
+    }
+
+    /// 按键的切换状态，macOS只记录CapsLock的状态，其他按键返回None
+    pub fn toggled(key: KeyMouse) -> Option<bool> {
+        match key {
+            KeyMouse::CapsLock => {
+                let flags =
+                    unsafe { CGEventSourceFlagsState(CGEventSourceStateID::HIDSystemState) };
+                Some(flags & CGEventFlags::CGEventFlagAlphaShift.bits() != 0)
+            }
+            _ => None,
+        }
+    }
+}
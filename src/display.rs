@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+
+pub use platform::detect;
+
+/// 屏幕详情，由一个或多个显示器组成
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Display {
+    ///显示器列表，第一个为主显示器
+    pub monitors: Vec<Monitor>,
+}
+
+/// 显示器
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Monitor {
+    ///显示器左上角x坐标
+    #[serde(default)]
+    pub x: i64,
+    ///显示器左上角y坐标
+    #[serde(default)]
+    pub y: i64,
+    ///屏幕分辨率宽度
+    pub width: u64,
+    ///屏幕分辨率高度
+    pub height: u64,
+    ///缩放比例
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl Display {
+    /// 只有一个显示器的屏幕
+    pub fn new(width: u64, height: u64) -> Self {
+        Display {
+            monitors: vec![Monitor::new(0, 0, width, height)],
+        }
+    }
+
+    /// 坐标是否在某个显示器上
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        self.monitors.iter().any(|m| m.contains(x, y))
+    }
+
+    /// 所有显示器的外接矩形(左, 上, 右, 下)
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        self.monitors.iter().fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(left, top, right, bottom), m| {
+                let (l, t, r, b) = m.bounds();
+                (left.min(l), top.min(t), right.max(r), bottom.max(b))
+            },
+        )
+    }
+
+    /// 主显示器中心坐标
+    pub fn center(&self) -> (f64, f64) {
+        self.monitors
+            .first()
+            .map(|m| {
+                let (l, t, r, b) = m.bounds();
+                ((l + r) / 2.0, (t + b) / 2.0)
+            })
+            .unwrap_or_default()
+    }
+
+    /// 将坐标限制在最近的显示器上
+    pub fn clamp(&self, x: f64, y: f64) -> (f64, f64) {
+        if self.contains(x, y) {
+            return (x, y);
+        }
+        self.monitors
+            .iter()
+            .map(|m| m.clamp(x, y))
+            .min_by(|a, b| distance(*a, (x, y)).total_cmp(&distance(*b, (x, y))))
+            .unwrap_or((x, y))
+    }
+
+    /// 坐标所在的显示器
+    pub fn monitor_at(&self, x: f64, y: f64) -> Option<&Monitor> {
+        self.monitors.iter().find(|m| m.contains(x, y))
+    }
+}
+
+impl Monitor {
+    pub fn new(x: i64, y: i64, width: u64, height: u64) -> Self {
+        Monitor {
+            x,
+            y,
+            width,
+            height,
+            scale: default_scale(),
+        }
+    }
+
+    /// 显示器矩形(左, 上, 右, 下)，右、下边界不包含在显示器内
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (x, y) = (self.x as f64, self.y as f64);
+        (x, y, x + self.width as f64, y + self.height as f64)
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        let (l, t, r, b) = self.bounds();
+        x >= l && x < r && y >= t && y < b
+    }
+
+    pub fn clamp(&self, x: f64, y: f64) -> (f64, f64) {
+        let (l, t, r, b) = self.bounds();
+        (x.clamp(l, r - 1.0), y.clamp(t, b - 1.0))
+    }
+}
+
+fn distance((x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> f64 {
+    (x1 - x2).hypot(y1 - y2)
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::{Display, Monitor};
+    use anyhow::{anyhow, Result};
+    use std::{mem::size_of, ptr::null_mut};
+    use winapi::{
+        shared::{
+            minwindef::{BOOL, LPARAM, TRUE},
+            windef::{HDC, HMONITOR, LPRECT},
+            winerror::S_OK,
+        },
+        um::{
+            shellscalingapi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI},
+            winuser::{EnumDisplayMonitors, GetMonitorInfoW, MONITORINFO, MONITORINFOF_PRIMARY},
+        },
+    };
+
+    /// 缩放比例为1时的DPI
+    const BASE_DPI: f64 = 96.0;
+
+    /// 枚举所有显示器，缩放比例为每个显示器的有效DPI
+    pub fn detect() -> Result<Display> {
+        let mut monitors: Vec<Monitor> = vec![];
+        let ok = unsafe {
+            EnumDisplayMonitors(
+                null_mut(),
+                null_mut(),
+                Some(add_monitor),
+                &mut monitors as *mut Vec<Monitor> as LPARAM,
+            )
+        };
+        if ok == 0 || monitors.is_empty() {
+            return Err(anyhow!("EnumDisplayMonitors失败"));
+        }
+        Ok(Display { monitors })
+    }
+
+    unsafe extern "system" fn add_monitor(
+        monitor: HMONITOR,
+        _hdc: HDC,
+        _rect: LPRECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(data as *mut Vec<Monitor>);
+        let mut info: MONITORINFO = std::mem::zeroed();
+        info.cbSize = size_of::<MONITORINFO>() as u32;
+        if GetMonitorInfoW(monitor, &mut info) == 0 {
+            return TRUE;
+        }
+        let rect = info.rcMonitor;
+        let mut m = Monitor::new(
+            rect.left as i64,
+            rect.top as i64,
+            (rect.right - rect.left) as u64,
+            (rect.bottom - rect.top) as u64,
+        );
+        let (mut dpi_x, mut dpi_y) = (0, 0);
+        if GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) == S_OK {
+            m.scale = dpi_x as f64 / BASE_DPI;
+        }
+        //主显示器放在第一个
+        if info.dwFlags & MONITORINFOF_PRIMARY != 0 {
+            monitors.insert(0, m);
+        } else {
+            monitors.push(m);
+        }
+        TRUE
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{Display, Monitor};
+    use anyhow::{anyhow, Result};
+    use std::{ffi::CStr, ptr::null, slice};
+    use x11::{xlib, xrandr};
+
+    /// 缩放比例为1时的DPI
+    const BASE_DPI: f64 = 96.0;
+
+    /// 通过XRandR枚举所有显示器
+    ///
+    /// X11没有按显示器的缩放比例，所有显示器使用`Xft.dpi`换算的缩放比例。
+    pub fn detect() -> Result<Display> {
+        unsafe {
+            let display = xlib::XOpenDisplay(null());
+            if display.is_null() {
+                return Err(anyhow!("打开X11 Display失败"));
+            }
+            let scale = dpi_scale(display);
+            let mut count = 0;
+            let infos = xrandr::XRRGetMonitors(
+                display,
+                xlib::XDefaultRootWindow(display),
+                xlib::True,
+                &mut count,
+            );
+            let mut monitors = vec![];
+            if !infos.is_null() {
+                for info in slice::from_raw_parts(infos, count.max(0) as usize) {
+                    let mut m = Monitor::new(
+                        info.x as i64,
+                        info.y as i64,
+                        info.width as u64,
+                        info.height as u64,
+                    );
+                    m.scale = scale;
+                    //主显示器放在第一个
+                    if info.primary != 0 {
+                        monitors.insert(0, m);
+                    } else {
+                        monitors.push(m);
+                    }
+                }
+                xrandr::XRRFreeMonitors(infos);
+            }
+            xlib::XCloseDisplay(display);
+            if monitors.is_empty() {
+                return Err(anyhow!("XRandR没有返回显示器"));
+            }
+            Ok(Display { monitors })
+        }
+    }
+
+    /// `Xft.dpi`换算的缩放比例，未设置时为1
+    unsafe fn dpi_scale(display: *mut xlib::Display) -> f64 {
+        let value = xlib::XGetDefault(display, c"Xft".as_ptr(), c"dpi".as_ptr());
+        if value.is_null() {
+            return 1.0;
+        }
+        CStr::from_ptr(value)
+            .to_str()
+            .ok()
+            .and_then(|dpi| dpi.trim().parse::<f64>().ok())
+            .filter(|dpi| *dpi > 0.0)
+            .map_or(1.0, |dpi| dpi / BASE_DPI)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{Display, Monitor};
+    use anyhow::{anyhow, Result};
+    use core_graphics::display::CGDisplay;
+
+    /// 枚举所有显示器，坐标为逻辑点，缩放比例为像素与点的比例（Retina显示器为2）
+    pub fn detect() -> Result<Display> {
+        let ids = CGDisplay::active_displays()
+            .map_err(|e| anyhow!("CGGetActiveDisplayList失败: {}", e))?;
+        let main = CGDisplay::main().id;
+        let mut monitors = vec![];
+        for id in ids {
+            let display = CGDisplay::new(id);
+            let bounds = display.bounds();
+            let mut m = Monitor::new(
+                bounds.origin.x as i64,
+                bounds.origin.y as i64,
+                bounds.size.width as u64,
+                bounds.size.height as u64,
+            );
+            if bounds.size.width > 0.0 {
+                m.scale = display.pixels_wide() as f64 / bounds.size.width;
+            }
+            //主显示器放在第一个
+            if id == main {
+                monitors.insert(0, m);
+            } else {
+                monitors.push(m);
+            }
+        }
+        if monitors.is_empty() {
+            return Err(anyhow!("没有可用的显示器"));
+        }
+        Ok(Display { monitors })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Display, Monitor};
+
+    #[test]
+    fn test_multi_monitor() {
+        //主显示器右侧有一个位置偏下的竖屏
+        let display = Display {
+            monitors: vec![
+                Monitor::new(0, 0, 1920, 1080),
+                Monitor::new(1920, 200, 1080, 1920),
+            ],
+        };
+        assert_eq!(display.bounds(), (0.0, 0.0, 3000.0, 2120.0));
+        assert_eq!(display.center(), (960.0, 540.0));
+        assert!(display.contains(2500.0, 1500.0));
+        assert!(!display.contains(2500.0, 100.0));
+        assert_eq!(display.clamp(2500.0, 100.0), (2500.0, 200.0));
+        assert_eq!(display.clamp(500.0, 1500.0), (500.0, 1079.0));
+        assert_eq!(
+            display.monitor_at(2000.0, 300.0),
+            Some(&display.monitors[1])
+        );
+    }
+}
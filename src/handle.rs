@@ -0,0 +1,200 @@
+use anyhow::Result;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    load_config,
+    sources::ConfigSources,
+    status::{Callback, Status},
+    Client, Config, ConfigMode, Server,
+};
+
+/// 嵌入其他程序时使用的启动器，按配置的mode启动服务端或客户端
+///
+/// ```no_run
+/// let handle = minput_mirror::Builder::from_file("config.yaml")?
+///     .on_status(|status| println!("{:?}", status))
+///     .start()?;
+/// // ...
+/// handle.shutdown()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Builder {
+    config: Config,
+    capture: bool,
+    watch: Option<ConfigSources>,
+    callbacks: Vec<Callback>,
+}
+
+impl Builder {
+    pub fn new(config: Config) -> Self {
+        Builder {
+            config,
+            capture: true,
+            watch: None,
+            callbacks: vec![],
+        }
+    }
+
+    /// 读取配置文件
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(load_config(path.as_ref())?))
+    }
+
+    /// 服务端是否监听本机鼠标键盘，默认监听；不监听时只接受客户端连接
+    pub fn capture_input(mut self, capture: bool) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    /// 合并分层配置，服务端监视其中的配置文件
+    pub fn from_sources(sources: ConfigSources) -> Result<Self> {
+        let mut builder = Self::new(sources.load()?.config);
+        builder.watch = Some(sources);
+        Ok(builder)
+    }
+
+    /// 服务端监视配置文件，修改后重新加载，结果通过`Status::Reloaded`/`Status::ReloadFailed`通知
+    pub fn watch_config<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.watch = Some(ConfigSources::new().local(path, true));
+        self
+    }
+
+    /// 状态变化回调，在工作线程中调用，不应长时间阻塞
+    pub fn on_status<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Status) + Send + Sync + 'static,
+    {
+        self.callbacks.push(Arc::new(f));
+        self
+    }
+
+    /// 启动服务，返回用于停止服务的句柄
+    pub fn start(self) -> Result<Handle> {
+        let callbacks = self.callbacks;
+        let notify = move |status: &Status| callbacks.iter().for_each(|f| f(status));
+        let instance = match self.config.mode {
+            ConfigMode::Server => {
+                let mut server = Server::new(self.config)?;
+                server.on_status(notify);
+                if let Some(sources) = self.watch {
+                    server.watch(sources)?;
+                }
+                server.start()?;
+                if self.capture {
+                    server.capture()?;
+                }
+                Instance::Server(Box::new(server))
+            }
+            ConfigMode::Client => {
+                let mut client = Client::new(self.config)?;
+                client.on_status(notify);
+                client.start()?;
+                Instance::Client(client)
+            }
+        };
+        Ok(Handle { instance })
+    }
+}
+
+enum Instance {
+    Server(Box<Server>),
+    Client(Client),
+}
+
+/// 运行中的服务，丢弃时同样会停止服务
+pub struct Handle {
+    instance: Instance,
+}
+
+impl Handle {
+    /// 服务端本地绑定的地址
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.instance {
+            Instance::Server(server) => server.local_addr(),
+            Instance::Client(_) => None,
+        }
+    }
+
+    /// 停止服务：等待工作线程退出并关闭端口，客户端释放按住的按键
+    pub fn shutdown(mut self) -> Result<()> {
+        match &mut self.instance {
+            Instance::Server(server) => {
+                server.stop();
+                Ok(())
+            }
+            Instance::Client(client) => client.stop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Builder;
+    use crate::{net::free_port, status::Status, Config};
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn wait_for(statuses: &Mutex<Vec<Status>>, f: impl Fn(&Status) -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if statuses.lock().unwrap().iter().any(&f) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_builder_shutdown() {
+        let server_status = Arc::new(Mutex::new(vec![]));
+        let recorder = server_status.clone();
+        let port = free_port();
+        let server = Builder::new(config(&format!(
+            "mode: server\nserver:\n  name: s\n  ip: 127.0.0.1\n  port: {}\ndisplay:\n  monitors:\n    - {{x: 0, y: 0, width: 1920, height: 1080}}\n",
+            port
+        )))
+        .capture_input(false)
+        .on_status(move |status| recorder.lock().unwrap().push(status.clone()))
+        .start()
+        .unwrap();
+        assert_eq!(server.local_addr().unwrap().port(), port);
+
+        let client_status = Arc::new(Mutex::new(vec![]));
+        let recorder = client_status.clone();
+        let client = Builder::new(config(&format!(
+            "mode: client\nclient:\n  name: c\n  server_ip: 127.0.0.1\n  server_port: {}\n  direction: right\ndisplay:\n  monitors:\n    - {{x: 0, y: 0, width: 1280, height: 720}}\n",
+            port
+        )))
+        .on_status(move |status| recorder.lock().unwrap().push(status.clone()))
+        .start()
+        .unwrap();
+
+        assert!(wait_for(&server_status, |s| matches!(
+            s,
+            Status::ClientConnected { name, .. } if name == "c"
+        )));
+        assert!(wait_for(&client_status, |s| *s
+            == Status::Connected {
+                server: "s".to_string()
+            }));
+
+        client.shutdown().unwrap();
+        server.shutdown().unwrap();
+        assert_eq!(client_status.lock().unwrap().last(), Some(&Status::Stopped));
+        assert_eq!(server_status.lock().unwrap().last(), Some(&Status::Stopped));
+        //端口已关闭，可以重新绑定
+        std::net::UdpSocket::bind(("127.0.0.1", port)).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+//! 键盘布局转换
+//!
+//! 按键以物理位置（按美式键盘命名）传输，两端键盘布局不同时，客户端把服务器按键换成
+//! 客户端布局中产生相同字符的按键。只比较无修饰键时产生的字符，需要不同修饰键才能输入的
+//! 字符保持原按键，这种情况可以使用文本输入模式。
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+use crate::net::protocol::KeyMouse;
+
+/// 默认键盘布局
+pub const DEFAULT_KEYBOARD_LAYOUT: &str = "us";
+
+/// 自定义键盘布局文件所在目录，文件名为`<布局名字>.yaml`
+const LAYOUT_DIR: &str = "layouts";
+
+/// 内置键盘布局
+const BUILTIN_LAYOUTS: [(&str, &str); 4] = [
+    ("us", include_str!("../layouts/us.yaml")),
+    ("uk", include_str!("../layouts/uk.yaml")),
+    ("de", include_str!("../layouts/de.yaml")),
+    ("fr", include_str!("../layouts/fr.yaml")),
+];
+
+/// 键盘布局
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyboardLayout {
+    /// 布局名字，握手时发送给对端
+    pub name: String,
+    /// 按键产生的字符，依次为无修饰键、Shift、AltGr
+    pub keys: HashMap<KeyMouse, Vec<String>>,
+}
+
+impl KeyboardLayout {
+    /// 加载键盘布局，`layout`为布局文件路径或布局名字
+    ///
+    /// 按名字加载时优先使用`layouts/<名字>.yaml`文件，其次使用内置布局。
+    pub fn load(layout: &str) -> Result<Self> {
+        let path = Path::new(layout);
+        if path.is_file() {
+            return Self::from_file(path);
+        }
+        let path = Path::new(LAYOUT_DIR).join(format!("{}.yaml", layout));
+        if path.is_file() {
+            return Self::from_file(&path);
+        }
+        BUILTIN_LAYOUTS
+            .iter()
+            .find(|(name, _)| *name == layout)
+            .map(|(_, yaml)| Self::from_yaml(yaml))
+            .unwrap_or_else(|| Err(anyhow!("unknown keyboard layout: {}", layout)))
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let yaml = std::fs::read_to_string(path)?;
+        Self::from_yaml(&yaml).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    fn from_yaml(yaml: &str) -> Result<Self> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// 无修饰键时按键产生的字符
+    fn base(&self, key: KeyMouse) -> Option<&str> {
+        self.keys
+            .get(&key)
+            .and_then(|levels| levels.first())
+            .map(String::as_str)
+    }
+}
+
+/// 服务器布局到客户端布局的按键转换表
+#[derive(Debug, Default)]
+pub struct KeyTranslation {
+    keys: HashMap<KeyMouse, KeyMouse>,
+}
+
+impl KeyTranslation {
+    pub fn new(from: &KeyboardLayout, to: &KeyboardLayout) -> Self {
+        let target: HashMap<&str, KeyMouse> = to
+            .keys
+            .keys()
+            .filter_map(|key| to.base(*key).map(|c| (c, *key)))
+            .collect();
+        let keys = from
+            .keys
+            .keys()
+            .filter_map(|key| {
+                let translated = *target.get(from.base(*key)?)?;
+                (translated != *key).then_some((*key, translated))
+            })
+            .collect();
+        KeyTranslation { keys }
+    }
+
+    /// 转换按键，没有对应按键时保持不变
+    pub fn translate(&self, key: KeyMouse) -> KeyMouse {
+        self.keys.get(&key).copied().unwrap_or(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyTranslation, KeyboardLayout, BUILTIN_LAYOUTS};
+    use crate::net::protocol::KeyMouse;
+
+    #[test]
+    fn test_builtin_layouts() {
+        for (name, _) in BUILTIN_LAYOUTS {
+            assert_eq!(KeyboardLayout::load(name).unwrap().name, name);
+        }
+        assert!(KeyboardLayout::load("xx").is_err());
+    }
+
+    #[test]
+    fn test_translation() {
+        let us = KeyboardLayout::load("us").unwrap();
+        let de = KeyboardLayout::load("de").unwrap();
+        let fr = KeyboardLayout::load("fr").unwrap();
+
+        let translation = KeyTranslation::new(&us, &de);
+        assert_eq!(translation.translate(KeyMouse::KeyY), KeyMouse::KeyZ);
+        assert_eq!(translation.translate(KeyMouse::KeyZ), KeyMouse::KeyY);
+        assert_eq!(translation.translate(KeyMouse::Minus), KeyMouse::Slash);
+        assert_eq!(translation.translate(KeyMouse::KeyA), KeyMouse::KeyA);
+        //德式键盘需要Shift才能输入分号，保持原按键
+        assert_eq!(
+            translation.translate(KeyMouse::SemiColon),
+            KeyMouse::SemiColon
+        );
+        assert_eq!(translation.translate(KeyMouse::Return), KeyMouse::Return);
+
+        let translation = KeyTranslation::new(&us, &fr);
+        assert_eq!(translation.translate(KeyMouse::KeyA), KeyMouse::KeyQ);
+        assert_eq!(translation.translate(KeyMouse::KeyM), KeyMouse::SemiColon);
+    }
+
+    #[test]
+    fn test_layout_file() {
+        let path = std::env::temp_dir().join("minput-test-layout.yaml");
+        std::fs::write(&path, "name: custom\nkeys:\n  KeyA: [b, B]\n").unwrap();
+        let layout = KeyboardLayout::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(layout.name, "custom");
+        assert_eq!(layout.keys[&KeyMouse::KeyA], vec!["b", "B"]);
+    }
+}
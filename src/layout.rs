@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use crate::{ConfigClientDirection, ConfigLayoutLink};
+
+/// 屏幕布局，记录每个屏幕在各个方向上相邻的屏幕
+#[derive(Debug, Default)]
+pub struct Layout {
+    links: HashMap<(String, ConfigClientDirection), Neighbor>,
+}
+
+/// 相邻屏幕
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbor {
+    /// 相邻屏幕名字
+    pub screen: String,
+    /// 相邻屏幕沿边缘方向的偏移
+    pub offset: EdgeOffset,
+    /// 允许切换的边缘范围
+    pub range: EdgeRange,
+}
+
+/// 沿边缘方向的偏移像素，用于物理位置未对齐的显示器
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EdgeOffset {
+    /// 偏移像素，正数表示进入的屏幕偏右/偏下
+    pub pixels: f64,
+    /// 偏移像素以进入的屏幕为单位，否则以离开的屏幕为单位
+    pub target: bool,
+}
+
+/// 允许切换的边缘范围，沿边缘方向的比例(0~1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeRange {
+    pub start: f64,
+    pub end: f64,
+    /// 范围以进入的屏幕边缘为准，否则以离开的屏幕边缘为准
+    pub target: bool,
+}
+
+impl Default for EdgeRange {
+    fn default() -> Self {
+        EdgeRange {
+            start: 0.0,
+            end: 1.0,
+            target: false,
+        }
+    }
+}
+
+impl EdgeRange {
+    /// 边缘上的比例位置是否在范围内
+    pub fn contains(&self, ratio: f64) -> bool {
+        ratio >= self.start && ratio <= self.end
+    }
+}
+
+impl Neighbor {
+    /// 没有偏移、整个边缘都可以切换的相邻屏幕
+    pub fn new(screen: String) -> Self {
+        Neighbor {
+            screen,
+            offset: EdgeOffset::default(),
+            range: EdgeRange::default(),
+        }
+    }
+}
+
+impl Layout {
+    /// 根据配置创建布局，每条连接同时生成反向连接
+    pub fn new(links: &[ConfigLayoutLink]) -> Self {
+        let mut layout = Layout::default();
+        for link in links {
+            layout.link(link);
+        }
+        layout
+    }
+
+    /// 添加连接：`to`屏幕位于`from`屏幕的`direction`方向
+    pub fn link(&mut self, link: &ConfigLayoutLink) {
+        let offset = link.offset as f64;
+        let range = EdgeRange {
+            start: link.start / 100.0,
+            end: link.end / 100.0,
+            target: false,
+        };
+        self.links.insert(
+            (link.from.clone(), link.direction),
+            Neighbor {
+                screen: link.to.clone(),
+                offset: EdgeOffset {
+                    pixels: offset,
+                    target: true,
+                },
+                range,
+            },
+        );
+        //反向连接使用同一个偏移和范围，以from屏幕为准
+        self.links.insert(
+            (link.to.clone(), link.direction.opposite()),
+            Neighbor {
+                screen: link.from.clone(),
+                offset: EdgeOffset {
+                    pixels: -offset,
+                    target: false,
+                },
+                range: EdgeRange {
+                    target: true,
+                    ..range
+                },
+            },
+        );
+    }
+
+    /// 获取屏幕在指定方向上相邻的屏幕
+    pub fn neighbor(&self, screen: &str, direction: ConfigClientDirection) -> Option<&Neighbor> {
+        self.links.get(&(screen.to_string(), direction))
+    }
+
+    /// 布局中是否存在该屏幕
+    pub fn contains(&self, screen: &str) -> bool {
+        self.links.keys().any(|(name, _)| name == screen)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Layout;
+    use crate::{ConfigClientDirection, ConfigLayoutLink};
+
+    fn link(from: &str, direction: ConfigClientDirection, to: &str) -> ConfigLayoutLink {
+        ConfigLayoutLink {
+            from: from.to_string(),
+            direction,
+            to: to.to_string(),
+            offset: 0,
+            start: 0.0,
+            end: 100.0,
+        }
+    }
+
+    #[test]
+    fn test_layout_chain() {
+        let layout = Layout::new(&[
+            link("server", ConfigClientDirection::Right, "a"),
+            link("a", ConfigClientDirection::Down, "b"),
+        ]);
+        assert_eq!(
+            layout
+                .neighbor("server", ConfigClientDirection::Right)
+                .map(|n| n.screen.as_str()),
+            Some("a")
+        );
+        assert_eq!(
+            layout
+                .neighbor("a", ConfigClientDirection::Left)
+                .map(|n| n.screen.as_str()),
+            Some("server")
+        );
+        assert_eq!(
+            layout
+                .neighbor("a", ConfigClientDirection::Down)
+                .map(|n| n.screen.as_str()),
+            Some("b")
+        );
+        assert_eq!(
+            layout
+                .neighbor("b", ConfigClientDirection::Up)
+                .map(|n| n.screen.as_str()),
+            Some("a")
+        );
+        assert_eq!(layout.neighbor("b", ConfigClientDirection::Left), None);
+        assert!(layout.contains("b"));
+        assert!(!layout.contains("c"));
+    }
+}
@@ -1,46 +1,278 @@
-use env_logger::{fmt::Color, Builder, Env};
-use lazy_static::lazy_static;
-use log::{error, info};
+use anyhow::{anyhow, Result};
+use env_logger::{fmt::Color, Env};
+use log::{error, info, warn};
+use net::protocol::KeyMouse;
 use rdev::display_size;
 use serde::{Deserialize, Serialize};
-use std::io::Write;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+pub use control::{send_request, ClientStatus, ControlRequest, ControlResponse};
+pub use dev::{client::Client, server::Server};
+pub use display::{Display, Monitor};
+pub use handle::{Builder, Handle};
+use keyboard::{KeyboardLayout, DEFAULT_KEYBOARD_LAYOUT};
+pub use net::discovery::{discover, DiscoveredServer, DISCOVERY_PORT, DISCOVERY_TIMEOUT};
+pub use sources::{ConfigSource, ConfigSources, LayeredConfig, ENV_PREFIX, LOCAL_CONFIG};
+pub use status::Status;
+pub use validate::{ConfigError, ConfigErrors};
+mod control;
 mod dev;
+mod display;
+mod handle;
+mod keyboard;
+mod layout;
+mod metrics;
 mod net;
+mod sources;
+mod status;
+mod validate;
 
-lazy_static! {
-    ///配置对象
-    static ref CONFIG: Config = get_config();
-    static ref DISPLAY: Display = get_display();
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub mode: String,
+    pub mode: ConfigMode,
     pub server: Option<ConfigServer>,
     pub client: Option<ConfigClient>,
+    ///显示器列表，未配置时自动枚举系统的显示器（无显示器的环境或检测不准确时可以通过配置指定）
+    pub display: Option<Display>,
+    ///键盘布局名字（us、uk、de、fr或layouts目录下的文件名）或布局文件路径，默认us
+    pub keyboard_layout: Option<String>,
+    ///本机HTTP指标端口，只监听127.0.0.1，通过`/metrics`输出Prometheus格式的指标，未配置时不开启
+    pub metrics_port: Option<u16>,
+}
+
+/// 运行模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigMode {
+    ///共享本机鼠标键盘
+    #[serde(rename = "server")]
+    Server,
+    ///接收服务端的鼠标键盘事件
+    #[serde(rename = "client")]
+    Client,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigServer {
-    ///服务器监听地址
-    pub ip: String,
+    ///服务器屏幕名字，布局中使用该名字指代服务器屏幕
+    #[serde(default = "default_server_name")]
+    pub name: String,
+    ///服务器监听地址，可以是一个或多个IP地址（IPv6链路本地地址可以带%网卡）或主机名，
+    ///如`0.0.0.0`、`[0.0.0.0, "::"]`、`fe80::1%eth0`
+    #[serde(deserialize_with = "one_or_many")]
+    pub ip: Vec<String>,
     ///服务器监听端口
     pub port: u16,
+    ///屏幕布局，未出现在布局中的客户端按客户端配置的direction放置在服务器屏幕旁
+    #[serde(default)]
+    pub layout: Vec<ConfigLayoutLink>,
+    ///按客户端名字配置的客户端设置
+    #[serde(default, serialize_with = "sorted")]
+    pub clients: HashMap<String, ConfigServerClient>,
+    ///切换相对移动模式的组合键，如[ControlLeft, Alt, KeyR]
+    #[serde(default)]
+    pub relative_mouse_hotkey: Vec<KeyMouse>,
+    ///全局热键，触发热键的按键不会发送给客户端
+    #[serde(default)]
+    pub hotkeys: Vec<ConfigHotkey>,
+    ///锁定键，如ScrollLock，该键打开（指示灯亮）期间光标锁定在当前屏幕，该键不发送给客户端
+    pub lock_key: Option<KeyMouse>,
+    ///屏幕边缘切换条件
+    #[serde(default)]
+    pub edge_guard: ConfigEdgeGuard,
+    ///是否回复客户端的发现探测，默认回复
+    #[serde(default = "default_discovery")]
+    pub discovery: bool,
+    ///发现端口，在监听地址上绑定
+    #[serde(default = "default_discovery_port")]
+    pub discovery_port: u16,
+    ///控制接口的Unix域套接字路径，未配置时不开启，`minput-mirror ctl`通过该接口查询、控制服务端
+    pub control_socket: Option<PathBuf>,
+}
+
+/// 屏幕边缘切换条件，默认到达边缘立即切换
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfigEdgeGuard {
+    ///光标在边缘停留的最短时间（毫秒）
+    pub dwell: u64,
+    ///是否需要两次触碰边缘才切换
+    pub double_tap: bool,
+    ///两次触碰边缘的最长间隔（毫秒）
+    pub double_tap_interval: u64,
+    ///边缘两端不切换的角落大小（像素）
+    pub corner: u64,
+    ///按住鼠标按钮（拖拽）时是否允许切换
+    pub drag: bool,
+}
+
+impl Default for ConfigEdgeGuard {
+    fn default() -> Self {
+        ConfigEdgeGuard {
+            dwell: 0,
+            double_tap: false,
+            double_tap_interval: 500,
+            corner: 0,
+            drag: false,
+        }
+    }
+}
+
+/// 服务器全局热键：同时按下`keys`时执行`action`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigHotkey {
+    pub keys: Vec<KeyMouse>,
+    #[serde(flatten)]
+    pub action: ConfigHotkeyAction,
+}
+
+/// 热键动作
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action")]
+pub enum ConfigHotkeyAction {
+    ///切换到指定名字的屏幕
+    #[serde(rename = "switch")]
+    Switch { screen: String },
+    ///按服务器、客户端连接顺序切换到下一个屏幕
+    #[serde(rename = "cycle")]
+    Cycle,
+    ///回到服务器屏幕
+    #[serde(rename = "server")]
+    Server,
+    ///锁定/解锁光标在当前屏幕
+    #[serde(rename = "lock")]
+    Lock,
+    ///切换相对移动模式
+    #[serde(rename = "relative")]
+    Relative,
+}
+
+/// 服务器端针对单个客户端的设置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigServerClient {
+    ///鼠标移动模式
+    #[serde(default)]
+    pub mouse_mode: ConfigMouseMode,
+    ///键盘输入模式
+    #[serde(default)]
+    pub keyboard_mode: ConfigKeyboardMode,
+    ///按键重映射规则，发送给该客户端前按规则转换按键
+    #[serde(default)]
+    pub keymap: Vec<ConfigKeymapRule>,
+}
+
+/// 按键重映射规则：同时按住`from`中的按键时，客户端收到`to`中的按键
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigKeymapRule {
+    pub from: Vec<KeyMouse>,
+    pub to: Vec<KeyMouse>,
+}
+
+/// 鼠标移动模式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigMouseMode {
+    ///发送光标绝对坐标
+    #[default]
+    #[serde(rename = "absolute")]
+    Absolute,
+    ///发送光标移动偏移量，服务器光标保持在屏幕中心，用于游戏、三维软件等捕获光标的程序
+    #[serde(rename = "relative")]
+    Relative,
+}
+
+/// 键盘输入模式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigKeyboardMode {
+    ///发送按键，客户端模拟相同的按键
+    #[default]
+    #[serde(rename = "keys")]
+    Keys,
+    ///可输入字符的按键发送产生的文本，客户端直接输入文本，用于输入法和两端键盘布局不同的情况
+    #[serde(rename = "text")]
+    Text,
+}
+
+fn default_server_name() -> String {
+    "server".to_string()
+}
+
+/// 单个字符串或字符串列表
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
+
+/// 按键排序输出，使`--print-config`的结果稳定
+fn sorted<S, V>(map: &HashMap<String, V>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    V: Serialize,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+fn default_discovery() -> bool {
+    true
+}
+
+fn default_discovery_port() -> u16 {
+    DISCOVERY_PORT
+}
+
+/// 屏幕布局连接：`to`屏幕位于`from`屏幕的`direction`方向
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigLayoutLink {
+    pub from: String,
+    pub direction: ConfigClientDirection,
+    pub to: String,
+    ///to屏幕沿边缘方向的偏移像素（to屏幕像素），显示器物理位置未对齐时使用，正数表示to屏幕偏右/偏下
+    #[serde(default)]
+    pub offset: i64,
+    ///允许切换的边缘范围起点，from屏幕边缘的百分比（左/上端为0）
+    #[serde(default)]
+    pub start: f64,
+    ///允许切换的边缘范围终点，from屏幕边缘的百分比（右/下端为100）
+    #[serde(default = "default_edge_end")]
+    pub end: f64,
+}
+
+fn default_edge_end() -> f64 {
+    100.0
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigClient {
     /// 客户端名字
     pub name: String,
-    ///服务器地址
-    pub server_ip: String,
-    ///服务器端口
-    pub server_port: u16,
+    ///服务器地址：IP地址（IPv6链路本地地址可以带%网卡）或主机名，未配置时在局域网广播发现服务器
+    pub server_ip: Option<String>,
+    ///服务器端口，配置server_ip时需要
+    pub server_port: Option<u16>,
     /// 客户端所在服务器显示器方向
     pub direction: ConfigClientDirection,
+    ///只连接指纹相同的服务器，用于局域网中有多个服务器时
+    pub server_fingerprint: Option<String>,
+    ///发现端口，与服务器的discovery_port一致
+    #[serde(default = "default_discovery_port")]
+    pub discovery_port: u16,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ConfigClientDirection {
     #[serde(rename = "left")]
     Left,
@@ -52,17 +284,23 @@ pub enum ConfigClientDirection {
     Down,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Display {
-    ///屏幕分辨率宽度
-    pub width: u64,
-    ///屏幕分辨率高度
-    pub height: u64,
+impl FromStr for ConfigClientDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        serde_yaml::from_str(s).map_err(|_| anyhow!("方向错误: {}(允许值：left/right/up/down)", s))
+    }
 }
 
-impl Display {
-    pub fn new(width: u64, height: u64) -> Self {
-        Display { width, height }
+impl ConfigClientDirection {
+    /// 相反方向
+    pub fn opposite(self) -> Self {
+        match self {
+            ConfigClientDirection::Left => ConfigClientDirection::Right,
+            ConfigClientDirection::Right => ConfigClientDirection::Left,
+            ConfigClientDirection::Up => ConfigClientDirection::Down,
+            ConfigClientDirection::Down => ConfigClientDirection::Up,
+        }
     }
 }
 
@@ -70,7 +308,7 @@ impl Display {
 fn init_logger() {
     //默认INFO日志级别
     let env = Env::default().default_filter_or("trace");
-    Builder::from_env(env)
+    env_logger::Builder::from_env(env)
         .format(|buf, record| {
             let mut style = buf.style();
             match record.level() {
@@ -91,46 +329,105 @@ fn init_logger() {
                 record.args()
             )
         })
-        .init();
+        .try_init()
+        .ok();
 }
 
-///获取配置
-fn get_config() -> Config {
-    let f = std::fs::File::open("config.yaml").expect("读取配置文件失败");
-    let config: Config = serde_yaml::from_reader(f).expect("配置文件解析失败");
-    config
+///读取配置文件
+pub fn load_config(path: &Path) -> Result<Config> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("读取配置文件{}失败: {}", path.display(), e))?;
+    validate::check_duplicate_keys(&text, path.display())?;
+    serde_yaml::from_str(&text).map_err(|e| anyhow!("配置文件{}解析失败: {}", path.display(), e))
 }
 
-///获取屏幕分辨率
-fn get_display_size() -> (u64, u64) {
-    display_size().expect("获取屏幕分辨率失败")
+///检查配置，错误为包含所有问题的`ConfigErrors`
+pub fn check_config(config: &Config) -> Result<()> {
+    let errors = validate::validate(config);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigErrors(errors).into())
+    }
 }
 
-///获取屏幕详情
-fn get_display() -> Display {
-    let (w, h) = get_display_size();
-    Display::new(w, h)
+///获取屏幕详情，优先使用配置文件中的显示器列表，否则枚举系统的显示器
+fn load_display(config: &Config) -> Result<Display> {
+    if let Some(display) = &config.display {
+        return Ok(display.clone());
+    }
+    match display::detect() {
+        Ok(display) => Ok(display),
+        Err(e) => {
+            warn!("枚举显示器失败，只使用主显示器: {}", e);
+            let (w, h) = display_size().map_err(|e| anyhow!("获取屏幕分辨率失败: {:?}", e))?;
+            Ok(Display::new(w, h))
+        }
+    }
 }
 
-///启动服务
-fn start_service() {
-    let result = match CONFIG.mode.as_str() {
-        "server" => dev::server::start(),
-        "client" => dev::client::start(),
-        _ => {
-            panic!("配置文件mode错误(允许值：server/client)");
-        }
-    };
+///获取键盘布局
+fn load_keyboard_layout(config: &Config) -> Result<KeyboardLayout> {
+    let layout = config
+        .keyboard_layout
+        .as_deref()
+        .unwrap_or(DEFAULT_KEYBOARD_LAYOUT);
+    KeyboardLayout::load(layout).map_err(|e| anyhow!("加载键盘布局失败: {}", e))
+}
 
-    if let Err(e) = result {
-        error!("异常退出：{}", e);
+/// 运行时上下文，服务端、客户端实例各自持有，同一进程中可以运行多个实例
+#[derive(Debug)]
+struct Context {
+    config: Config,
+    display: Display,
+    keyboard_layout: KeyboardLayout,
+}
+
+impl Context {
+    ///检查配置并加载屏幕、键盘布局，配置问题返回`ConfigErrors`
+    fn new(config: Config) -> Result<Self> {
+        check_config(&config)?;
+        let display = load_display(&config)?;
+        let keyboard_layout = load_keyboard_layout(&config).map_err(|e| {
+            ConfigErrors(vec![ConfigError {
+                path: "keyboard_layout".to_string(),
+                message: e.to_string(),
+            }])
+        })?;
+        Ok(Context {
+            config,
+            display,
+            keyboard_layout,
+        })
     }
 }
 
-pub fn start() {
+///按配置启动服务端或客户端，阻塞直到服务退出；服务端监视`sources`中的配置文件，修改后重新加载
+pub fn run(config: Config, sources: Option<ConfigSources>) -> Result<()> {
     init_logger();
-    info!("config info: {:#?}", *CONFIG);
-    info!("display info: {:#?}", *DISPLAY);
-    start_service();
+    let result = match config.mode {
+        ConfigMode::Server => Server::new(config).and_then(|mut server| {
+            if let Some(sources) = sources {
+                server.watch(sources)?;
+            }
+            server.run()
+        }),
+        ConfigMode::Client => Client::new(config).and_then(|mut client| client.run()),
+    };
+    if let Err(e) = &result {
+        error!("异常退出：{}", e);
+    }
     info!("exit");
+    result
+}
+
+///按系统、用户、当前目录下的config.yaml和环境变量合并配置并启动服务
+pub fn start() {
+    let sources = ConfigSources::standard(None);
+    let result = sources
+        .load()
+        .and_then(|layered| run(layered.config, Some(sources)));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+    }
 }
@@ -1,5 +1,303 @@
-use minput_mirror::start;
+use clap::{Parser, Subcommand};
+use minput_mirror::{
+    check_config, discover, run, send_request, ConfigClientDirection, ConfigErrors, ConfigSources,
+    ControlRequest, DISCOVERY_PORT, DISCOVERY_TIMEOUT, LOCAL_CONFIG,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
 
-fn main() {
-    start();
+/// 运行时错误
+const EXIT_RUNTIME_ERROR: u8 = 1;
+/// 配置文件错误，命令行参数错误由clap以2退出
+const EXIT_CONFIG_ERROR: u8 = 3;
+
+/// 多终端鼠标键盘共享
+#[derive(Debug, Parser)]
+#[command(name = "minput-mirror", version)]
+struct Cli {
+    /// 本地配置文件路径，默认为当前目录下的config.yaml，依次合并系统、用户配置文件之后
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// 显示合并后的配置以及每个字段的来源
+    #[arg(long, global = true)]
+    print_config: bool,
+    /// 键盘布局，覆盖配置文件中的keyboard_layout
+    #[arg(long, global = true)]
+    keyboard_layout: Option<String>,
+    /// 未指定时按配置文件中的mode启动
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 以服务端模式启动
+    Server {
+        /// 服务器屏幕名字
+        #[arg(long)]
+        name: Option<String>,
+        /// 监听地址，可以指定多个，如--ip 0.0.0.0 --ip ::
+        #[arg(long)]
+        ip: Vec<String>,
+        /// 监听端口
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// 以客户端模式启动
+    Client {
+        /// 客户端名字
+        #[arg(long)]
+        name: Option<String>,
+        /// 服务器地址
+        #[arg(long)]
+        server_ip: Option<String>,
+        /// 服务器端口
+        #[arg(long)]
+        server_port: Option<u16>,
+        /// 客户端在服务器屏幕的哪个方向：left/right/up/down
+        #[arg(long)]
+        direction: Option<ConfigClientDirection>,
+    },
+    /// 检查配置文件
+    CheckConfig,
+    /// 在局域网中发现服务器
+    Discover {
+        /// 发现端口
+        #[arg(long, default_value_t = DISCOVERY_PORT)]
+        port: u16,
+        /// 探测地址，默认为局域网广播地址，可以指定多个
+        #[arg(long)]
+        target: Vec<String>,
+        /// 等待回复的时间（毫秒）
+        #[arg(long, default_value_t = DISCOVERY_TIMEOUT.as_millis() as u64)]
+        timeout: u64,
+    },
+    /// 通过控制接口查询、控制运行中的服务端
+    Ctl {
+        /// 控制接口路径，默认使用配置中的server.control_socket
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// 显示版本
+    Version,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum CtlCommand {
+    /// 列出已连接的客户端
+    Clients,
+    /// 显示当前激活的屏幕
+    Active,
+    /// 切换到指定屏幕
+    Switch { screen: String },
+    /// 断开客户端
+    Kick { name: String },
+    /// 锁定光标在当前屏幕
+    Lock,
+    /// 解锁光标
+    Unlock,
+    /// 重新加载配置文件
+    Reload,
+    /// 输出Prometheus文本格式的运行指标
+    Metrics,
+}
+
+impl From<CtlCommand> for ControlRequest {
+    fn from(command: CtlCommand) -> Self {
+        match command {
+            CtlCommand::Clients => ControlRequest::Clients,
+            CtlCommand::Active => ControlRequest::Active,
+            CtlCommand::Switch { screen } => ControlRequest::Switch { screen },
+            CtlCommand::Kick { name } => ControlRequest::Kick { name },
+            CtlCommand::Lock => ControlRequest::Lock,
+            CtlCommand::Unlock => ControlRequest::Unlock,
+            CtlCommand::Reload => ControlRequest::Reload,
+            CtlCommand::Metrics => ControlRequest::Metrics,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Some(Command::Version) = cli.command {
+        println!("minput-mirror {}", env!("CARGO_PKG_VERSION"));
+        return ExitCode::SUCCESS;
+    }
+    if let Some(Command::Discover {
+        port,
+        target,
+        timeout,
+    }) = &cli.command
+    {
+        return run_discover(*port, target, Duration::from_millis(*timeout));
+    }
+
+    let sources = apply_args(ConfigSources::standard(cli.config.as_deref()), &cli);
+    if let Some(Command::Ctl { socket, command }) = &cli.command {
+        let path = match socket {
+            Some(path) => path.clone(),
+            None => match sources
+                .load()
+                .map(|layered| layered.config.server.and_then(|s| s.control_socket))
+            {
+                Ok(Some(path)) => path,
+                Ok(None) => {
+                    eprintln!("未配置server.control_socket，请使用--socket指定控制接口");
+                    return ExitCode::from(EXIT_CONFIG_ERROR);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::from(EXIT_CONFIG_ERROR);
+                }
+            },
+        };
+        return run_ctl(&path, command.clone().into());
+    }
+    let layered = match sources.load() {
+        Ok(layered) => layered,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_CONFIG_ERROR);
+        }
+    };
+    if cli.print_config {
+        println!("{}", layered.describe());
+        return ExitCode::SUCCESS;
+    }
+    let name = cli
+        .config
+        .as_deref()
+        .unwrap_or_else(|| Path::new(LOCAL_CONFIG))
+        .display();
+    if let Err(e) = check_config(&layered.config) {
+        match e.downcast_ref::<ConfigErrors>() {
+            Some(errors) => eprintln!("{}", layered.describe_errors(errors)),
+            None => eprintln!("{}: {}", name, e),
+        }
+        return ExitCode::from(EXIT_CONFIG_ERROR);
+    }
+    if let Some(Command::CheckConfig) = cli.command {
+        println!("{}: ok", name);
+        return ExitCode::SUCCESS;
+    }
+
+    match run(layered.config, Some(sources)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            if e.chain().any(|cause| cause.is::<ConfigErrors>()) {
+                ExitCode::from(EXIT_CONFIG_ERROR)
+            } else {
+                ExitCode::from(EXIT_RUNTIME_ERROR)
+            }
+        }
+    }
+}
+
+/// 发现服务器并逐行输出名字、地址和指纹，没有发现服务器时以运行错误退出
+fn run_discover(port: u16, targets: &[String], timeout: Duration) -> ExitCode {
+    let mut addrs: Vec<SocketAddr> = vec![];
+    for target in targets {
+        match (target.as_str(), port).to_socket_addrs() {
+            Ok(resolved) => addrs.extend(resolved),
+            Err(e) => {
+                eprintln!("{}: {}", target, e);
+                return ExitCode::from(EXIT_RUNTIME_ERROR);
+            }
+        }
+    }
+    if addrs.is_empty() {
+        addrs.push((Ipv4Addr::BROADCAST, port).into());
+    }
+    let servers = match discover(&addrs, timeout) {
+        Ok(servers) => servers,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_RUNTIME_ERROR);
+        }
+    };
+    if servers.is_empty() {
+        eprintln!("未发现服务器");
+        return ExitCode::from(EXIT_RUNTIME_ERROR);
+    }
+    for server in servers {
+        println!("{}\t{}\t{}", server.name, server.addr, server.fingerprint);
+    }
+    ExitCode::SUCCESS
+}
+
+/// 发送控制请求，输出响应中的数据，请求失败时以运行错误退出
+fn run_ctl(path: &Path, request: ControlRequest) -> ExitCode {
+    match send_request(path, &request).and_then(|response| response.into_result()) {
+        //指标为文本，原样输出
+        Ok(Some(serde_json::Value::String(text))) => {
+            print!("{}", text);
+            ExitCode::SUCCESS
+        }
+        Ok(Some(data)) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&data).unwrap_or_default()
+            );
+            ExitCode::SUCCESS
+        }
+        Ok(None) => {
+            println!("ok");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+    }
+}
+
+/// 命令行参数作为最后一层覆盖配置文件和环境变量
+fn apply_args(mut sources: ConfigSources, cli: &Cli) -> ConfigSources {
+    if let Some(layout) = &cli.keyboard_layout {
+        sources = sources.arg("keyboard_layout", layout.as_str());
+    }
+    match &cli.command {
+        Some(Command::Server { name, ip, port }) => {
+            sources = sources.arg("mode", "server");
+            if let Some(name) = name {
+                sources = sources.arg("server.name", name.as_str());
+            }
+            if !ip.is_empty() {
+                sources = sources.arg("server.ip", ip.clone());
+            }
+            if let Some(port) = port {
+                sources = sources.arg("server.port", *port as u64);
+            }
+        }
+        Some(Command::Client {
+            name,
+            server_ip,
+            server_port,
+            direction,
+        }) => {
+            sources = sources.arg("mode", "client");
+            if let Some(name) = name {
+                sources = sources.arg("client.name", name.as_str());
+            }
+            if let Some(server_ip) = server_ip {
+                sources = sources.arg("client.server_ip", server_ip.as_str());
+            }
+            if let Some(server_port) = server_port {
+                sources = sources.arg("client.server_port", *server_port as u64);
+            }
+            if let Some(direction) = direction {
+                let value = serde_yaml::to_value(direction).unwrap_or_default();
+                sources = sources.arg("client.direction", value);
+            }
+        }
+        _ => {}
+    }
+    sources
 }
@@ -0,0 +1,321 @@
+use anyhow::{anyhow, Result};
+use log::{debug, error, warn};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::net::{
+    protocol::{Flag, Protocol},
+    server::POLL_INTERVAL,
+};
+
+/// 延迟直方图的桶上限（秒）
+const BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0,
+];
+
+/// 报文统计的方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Direction {
+    /// 发送
+    Sent,
+    /// 接收
+    Received,
+    /// 丢弃：没有接收者、发送失败或无法模拟
+    Dropped,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+            Direction::Dropped => "dropped",
+        }
+    }
+}
+
+/// 延迟直方图
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// 每个桶的计数，不累加
+    counts: [u64; BUCKETS.len()],
+    /// 超过最大桶的计数
+    overflow: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        match BUCKETS.iter().position(|le| seconds <= *le) {
+            Some(i) => self.counts[i] += 1,
+            None => self.overflow += 1,
+        }
+        self.sum += seconds;
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum::<u64>() + self.overflow
+    }
+
+    fn render(&self, name: &str, help: &str, out: &mut String) {
+        writeln!(out, "# HELP {} {}", name, help).ok();
+        writeln!(out, "# TYPE {} histogram", name).ok();
+        let mut cumulative = 0;
+        for (le, count) in BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative).ok();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count()).ok();
+        writeln!(out, "{}_sum {}", name, self.sum).ok();
+        writeln!(out, "{}_count {}", name, self.count()).ok();
+    }
+}
+
+#[derive(Debug, Default)]
+struct Data {
+    /// (方向, 标记, 鼠标键盘分类)的报文数
+    events: BTreeMap<(Direction, &'static str, &'static str), u64>,
+    /// 心跳往返时间
+    rtt: Histogram,
+    /// 客户端模拟鼠标键盘事件的耗时
+    inject: Histogram,
+}
+
+/// 服务端、客户端的运行指标，按Prometheus文本格式输出
+#[derive(Debug)]
+pub struct Metrics {
+    data: Mutex<Data>,
+    /// 心跳时间戳的起点
+    epoch: Instant,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            data: Mutex::default(),
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl Metrics {
+    /// 按报文的标记和鼠标键盘分类计数
+    pub fn count(&self, direction: Direction, protocol: &Protocol) {
+        let category = match protocol.flag {
+            Flag::KeyMouse => protocol.key_mouse.category(),
+            _ => "none",
+        };
+        if let Ok(mut data) = self.data.lock() {
+            *data
+                .events
+                .entry((direction, protocol.flag.name(), category))
+                .or_default() += 1;
+        }
+    }
+
+    /// 当前心跳时间戳（微秒）
+    pub fn timestamp(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// 收到心跳回复，按回复中的时间戳记录往返时间
+    pub fn observe_rtt(&self, timestamp: u64) {
+        let rtt = Duration::from_micros(self.timestamp().saturating_sub(timestamp));
+        debug!("heartbeat rtt {:?}", rtt);
+        if let Ok(mut data) = self.data.lock() {
+            data.rtt.observe(rtt);
+        }
+    }
+
+    /// 记录模拟鼠标键盘事件的耗时
+    pub fn observe_inject(&self, duration: Duration) {
+        if let Ok(mut data) = self.data.lock() {
+            data.inject.observe(duration);
+        }
+    }
+
+    /// Prometheus文本格式
+    pub fn render(&self) -> String {
+        let data = match self.data.lock() {
+            Ok(data) => data,
+            Err(e) => e.into_inner(),
+        };
+        let mut out = String::new();
+        out.push_str("# HELP minput_events_total Events by direction, protocol flag and key/mouse category.\n");
+        out.push_str("# TYPE minput_events_total counter\n");
+        for ((direction, flag, category), count) in &data.events {
+            writeln!(
+                out,
+                "minput_events_total{{direction=\"{}\",flag=\"{}\",category=\"{}\"}} {}",
+                direction.name(),
+                flag,
+                category,
+                count
+            )
+            .ok();
+        }
+        data.rtt.render(
+            "minput_heartbeat_rtt_seconds",
+            "Heartbeat round-trip time.",
+            &mut out,
+        );
+        data.inject.render(
+            "minput_inject_duration_seconds",
+            "Time spent simulating received input events.",
+            &mut out,
+        );
+        out
+    }
+}
+
+/// 输出指标的HTTP服务，只处理`GET /metrics`
+pub struct MetricsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// 在本机`port`端口启动HTTP服务
+    pub fn start(port: u16, metrics: Arc<Metrics>) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| anyhow!("监听指标端口{}失败: {}", port, e))?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("minput-metrics".to_string())
+                .spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                if let Err(e) = serve(stream, &metrics) {
+                                    debug!("metrics connection error: {}", e);
+                                }
+                            }
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                                thread::sleep(POLL_INTERVAL)
+                            }
+                            Err(e) => warn!("接受指标连接错误: {}", e),
+                        }
+                    }
+                })?
+        };
+        Ok(MetricsServer {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// 实际绑定的地址
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 停止并等待HTTP线程退出
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("metrics thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 处理一个HTTP请求后关闭连接
+fn serve(mut stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    //只需要请求行，请求头不超过缓冲区
+    let mut buf = [0u8; 1024];
+    let len = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let path = request.split_whitespace().nth(1);
+    let (status, body) = match (request.starts_with("GET "), path) {
+        (true, Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, Metrics, MetricsServer};
+    use crate::net::protocol::{Event, Flag, KeyMouse, Protocol};
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        sync::Arc,
+        time::Duration,
+    };
+
+    #[test]
+    fn test_render() {
+        let metrics = Arc::new(Metrics::default());
+        let mouse = Protocol::new(Flag::KeyMouse, KeyMouse::MouseMove, Event::Move(1.0, 1.0));
+        metrics.count(Direction::Sent, &mouse);
+        metrics.count(Direction::Sent, &mouse);
+        metrics.count(
+            Direction::Dropped,
+            &Protocol::new(Flag::KeyMouse, KeyMouse::KeyA, Event::Press),
+        );
+        metrics.count(Direction::Received, &Protocol::text("a"));
+        metrics.observe_rtt(metrics.timestamp());
+        metrics.observe_inject(Duration::from_millis(3));
+        metrics.observe_inject(Duration::from_secs(2));
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "minput_events_total{direction=\"sent\",flag=\"key_mouse\",category=\"move\"} 2"
+        ));
+        assert!(text.contains(
+            "minput_events_total{direction=\"dropped\",flag=\"key_mouse\",category=\"keyboard\"} 1"
+        ));
+        assert!(text.contains(
+            "minput_events_total{direction=\"received\",flag=\"text\",category=\"none\"} 1"
+        ));
+        assert!(text.contains("minput_heartbeat_rtt_seconds_count 1"));
+        assert!(text.contains("minput_inject_duration_seconds_bucket{le=\"0.002\"} 0"));
+        assert!(text.contains("minput_inject_duration_seconds_bucket{le=\"0.005\"} 1"));
+        assert!(text.contains("minput_inject_duration_seconds_bucket{le=\"+Inf\"} 2"));
+
+        let mut server = MetricsServer::start(0, metrics).unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(&text));
+        server.stop();
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs, UdpSocket},
+};
+
+/// 解析地址：IP地址、带网卡的IPv6链路本地地址（如`fe80::1%eth0`、`fe80::1%2`）或主机名
+///
+/// IPv6地址可以带方括号。主机名通过系统解析，可能得到多个地址。
+pub fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    if let Some(addr) = parse_ip(host, port)? {
+        return Ok(vec![addr]);
+    }
+    check_hostname(host)?;
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("解析{}失败: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(anyhow!("{}没有可用的地址", host));
+    }
+    debug!("resolve {} to {:?}", host, addrs);
+    Ok(addrs)
+}
+
+/// 检查地址格式，不解析主机名
+pub fn check(host: &str) -> Result<()> {
+    match parse_ip(host, 0)? {
+        Some(_) => Ok(()),
+        None => check_hostname(host),
+    }
+}
+
+/// 解析IP地址，不是IP地址时返回None
+fn parse_ip(host: &str, port: u16) -> Result<Option<SocketAddr>> {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let (ip, scope) = match host.split_once('%') {
+        Some((ip, scope)) => (ip, Some(scope)),
+        None => (host, None),
+    };
+    match (ip.parse::<IpAddr>(), scope) {
+        (Ok(ip), None) => Ok(Some(SocketAddr::new(ip, port))),
+        (Ok(IpAddr::V6(ip)), Some(scope)) => {
+            let scope_id = scope_id(scope)?;
+            Ok(Some(SocketAddrV6::new(ip, port, 0, scope_id).into()))
+        }
+        (Ok(IpAddr::V4(_)), Some(_)) => Err(anyhow!("{}: IPv4地址不能指定网卡", host)),
+        (Err(_), Some(_)) => Err(anyhow!("{}: 只有IPv6地址可以指定网卡", host)),
+        (Err(_), None) => Ok(None),
+    }
+}
+
+/// 网卡序号，可以是数字或网卡名
+fn scope_id(scope: &str) -> Result<u32> {
+    if let Ok(id) = scope.parse() {
+        return Ok(id);
+    }
+    interface_index(scope).ok_or_else(|| anyhow!("网卡{}不存在", scope))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    //SAFETY: name为以0结尾的字符串
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+/// Windows上网卡需要使用序号
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+fn check_hostname(host: &str) -> Result<()> {
+    let valid = !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        //最后一段全为数字的是写错的IPv4地址，如0.0.0.256
+        && !host
+            .rsplit('.')
+            .next()
+            .is_some_and(|label| label.chars().all(|c| c.is_ascii_digit()));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("{}: 不是有效的IP地址或主机名", host))
+    }
+}
+
+/// 与`addr`同一协议族的未指定地址，端口由系统分配
+pub fn unspecified(addr: &SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+/// 绑定所有监听地址
+///
+/// 支持双栈的系统上`::`同时接收IPv4报文，此时同一端口的`0.0.0.0`会绑定失败，跳过该地址。
+pub fn bind_all(hosts: &[String], port: u16) -> Result<Vec<UdpSocket>> {
+    let mut sockets: Vec<UdpSocket> = vec![];
+    for host in hosts {
+        for addr in resolve(host, port)? {
+            match UdpSocket::bind(addr) {
+                Ok(socket) => sockets.push(socket),
+                Err(e) if e.kind() == ErrorKind::AddrInUse && covered(&sockets, &addr) => {
+                    info!("{} is covered by dual-stack socket", addr);
+                }
+                Err(e) => return Err(anyhow!("绑定{}失败: {}", addr, e)),
+            }
+        }
+    }
+    if sockets.is_empty() {
+        return Err(anyhow!("没有监听地址"));
+    }
+    Ok(sockets)
+}
+
+/// IPv4地址是否已由同一端口的IPv6未指定地址套接字接收
+fn covered(sockets: &[UdpSocket], addr: &SocketAddr) -> bool {
+    addr.is_ipv4()
+        && sockets.iter().any(|socket| {
+            socket.local_addr().is_ok_and(|local| {
+                local.ip() == Ipv6Addr::UNSPECIFIED && local.port() == addr.port()
+            })
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bind_all, check, resolve};
+    use crate::net::free_port;
+    use std::net::{SocketAddr, SocketAddrV6};
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve("::1", 48899).unwrap(),
+            vec!["[::1]:48899".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(
+            resolve("[::1]", 48899).unwrap(),
+            resolve("::1", 48899).unwrap()
+        );
+        let addr = resolve("fe80::1%3", 48899).unwrap()[0];
+        assert_eq!(
+            addr,
+            SocketAddrV6::new("fe80::1".parse().unwrap(), 48899, 0, 3).into()
+        );
+        #[cfg(target_os = "linux")]
+        {
+            let SocketAddr::V6(addr) = resolve("fe80::1%lo", 48899).unwrap()[0] else {
+                panic!("not ipv6");
+            };
+            assert_ne!(addr.scope_id(), 0);
+        }
+        assert!(resolve("localhost", 48899)
+            .unwrap()
+            .iter()
+            .all(|addr| addr.ip().is_loopback()));
+        assert!(resolve("fe80::1%no-such-interface0", 1).is_err());
+        assert!(check("127.0.0.1%1").is_err());
+        assert!(check("server.local").is_ok());
+        assert!(check("bad host").is_err());
+        assert!(check("0.0.0.256").is_err());
+    }
+
+    #[test]
+    fn test_bind_ipv6() {
+        let port = free_port();
+        let sockets = bind_all(&["::1".to_string(), "127.0.0.1".to_string()], port).unwrap();
+        let addrs: Vec<_> = sockets.iter().map(|s| s.local_addr().unwrap()).collect();
+        assert_eq!(
+            addrs,
+            vec![
+                format!("[::1]:{}", port).parse::<SocketAddr>().unwrap(),
+                format!("127.0.0.1:{}", port).parse().unwrap(),
+            ]
+        );
+    }
+}
@@ -1 +1,52 @@
+use anyhow::Result;
+use log::debug;
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
 
+use super::{
+    addr::unspecified,
+    protocol::{Protocol, PROTOCOL_MAX_LEN},
+};
+
+pub struct UdpClient {
+    socket: UdpSocket,
+}
+
+impl UdpClient {
+    /// 连接服务器，按服务器地址的协议族绑定本地地址
+    pub fn connect(server: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(unspecified(&server))?;
+        socket.connect(server)?;
+        debug!("UdpSocket {} connect to {}", socket.local_addr()?, server);
+        Ok(Self { socket })
+    }
+
+    /// 本地绑定的地址
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    pub fn send(&self, protocol: Protocol) -> Result<()> {
+        self.socket.send(&protocol.to_vec())?;
+        Ok(())
+    }
+
+    /// 设置接收超时，None为一直阻塞
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.socket.set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// 接收服务器报文，超时返回None
+    pub fn recv(&self) -> Result<Option<Protocol>> {
+        let mut buf = [0u8; PROTOCOL_MAX_LEN];
+        match self.socket.recv(&mut buf) {
+            Ok(len) => Ok(Some(Protocol::from(&buf[..len]))),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
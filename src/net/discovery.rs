@@ -0,0 +1,361 @@
+use anyhow::{anyhow, Result};
+use log::{debug, error, warn};
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    addr::{bind_all, unspecified},
+    protocol::{Announce, Flag, Protocol, PROTOCOL_MAX_LEN},
+    server::POLL_INTERVAL,
+};
+
+/// 默认的发现端口
+pub const DISCOVERY_PORT: u16 = 48898;
+/// 保存服务器标识的文件名，位于用户配置目录
+pub const SERVER_ID_FILE: &str = "server_id";
+/// 默认的发现等待时间
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+/// 等待回复时检查套接字的间隔
+const DISCOVERY_POLL: Duration = Duration::from_millis(10);
+
+/// 发现的服务器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    /// 服务器屏幕名字
+    pub name: String,
+    /// 服务地址，IP为回复报文的来源地址
+    pub addr: SocketAddr,
+    /// 服务器指纹
+    pub fingerprint: String,
+}
+
+/// 按服务器名字和本机标识计算指纹（FNV-1a），只用于区分服务器，不能防止伪造
+pub fn fingerprint(name: &str, id: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes().chain([0]).chain(id.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// 读取`path`中保存的服务器标识，不存在时生成随机标识并保存，使指纹在重启、更换显示器后保持不变
+pub fn load_id(path: &Path) -> Result<String> {
+    if let Ok(id) = fs::read_to_string(path) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+    let id = random_id();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, &id).map_err(|e| anyhow!("保存服务器标识{}失败: {}", path.display(), e))?;
+    Ok(id)
+}
+
+/// 128位随机标识，随机数来自标准库HashMap的随机种子和当前时间
+fn random_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    let part = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", part(), part())
+}
+
+/// 服务端的发现应答线程，回复客户端广播的探测
+pub struct DiscoveryResponder {
+    sockets: Vec<Arc<UdpSocket>>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl DiscoveryResponder {
+    /// 在未指定地址上绑定发现端口并启动应答线程，`listen`为服务实际监听的地址
+    ///
+    /// 绑定具体地址时部分平台收不到局域网广播，因此按`listen`的协议族绑定`::`、`0.0.0.0`，
+    /// 只回复能通过监听地址连接服务的探测：回复的来源地址即客户端连接的地址，必须在监听地址中。
+    pub fn start(listen: &[SocketAddr], port: u16, announce: Announce) -> Result<Self> {
+        let response = Protocol::discovery_response(&announce)?.to_vec();
+        let stop = Arc::new(AtomicBool::new(false));
+        let listen: Arc<[IpAddr]> = listen.iter().map(SocketAddr::ip).collect();
+        let mut hosts = vec![];
+        if listen.iter().any(IpAddr::is_ipv6) {
+            hosts.push("::".to_string());
+        }
+        if listen.iter().any(IpAddr::is_ipv4) {
+            hosts.push("0.0.0.0".to_string());
+        }
+        let (mut sockets, mut threads) = (vec![], vec![]);
+        for socket in bind_all(&hosts, port)? {
+            socket.set_read_timeout(Some(POLL_INTERVAL))?;
+            debug!("discovery bind to {}", socket.local_addr()?);
+            let socket = Arc::new(socket);
+            sockets.push(socket.clone());
+            let (stop, response, listen) = (stop.clone(), response.clone(), listen.clone());
+            threads.push(
+                thread::Builder::new()
+                    .name("minput-discovery".to_string())
+                    .spawn(move || respond(&socket, &response, &listen, &stop))?,
+            );
+        }
+        Ok(DiscoveryResponder {
+            sockets,
+            stop,
+            threads,
+        })
+    }
+
+    /// 第一个监听地址实际绑定的地址
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.sockets[0].local_addr()?)
+    }
+
+    /// 停止并等待应答线程退出
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("discovery thread panicked");
+            }
+        }
+    }
+}
+
+/// 回复收到的探测，直到`stop`置位
+fn respond(socket: &UdpSocket, response: &[u8], listen: &[IpAddr], stop: &AtomicBool) {
+    let mut buf = [0u8; PROTOCOL_MAX_LEN];
+    while !stop.load(Ordering::Relaxed) {
+        let (len, addr) = match socket.recv_from(&mut buf) {
+            Ok(recv) => recv,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                warn!("接收发现探测错误: {:?}", e);
+                continue;
+            }
+        };
+        if Protocol::from(&buf[..len]).flag != Flag::DiscoveryRequest {
+            continue;
+        }
+        debug!("discovery probe from {}", addr);
+        if !reachable(listen, addr) {
+            debug!("{} can not reach listen address {:?}", addr, listen);
+            continue;
+        }
+        if let Err(e) = socket.send_to(response, addr) {
+            warn!("reply discovery {} error: {}", addr, e);
+        }
+    }
+}
+
+/// 回复`from`时使用的本机地址是否为监听地址
+fn reachable(listen: &[IpAddr], from: SocketAddr) -> bool {
+    let local = match route(from) {
+        Ok(local) => local,
+        Err(e) => {
+            warn!("route to {} error: {}", from, e);
+            return false;
+        }
+    };
+    listen.iter().any(|ip| match ip {
+        //没有IPv4监听地址时`::`为双栈套接字，同时接收IPv4报文
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            local.is_ipv6() || !listen.iter().any(IpAddr::is_ipv4)
+        }
+        IpAddr::V4(ip) if ip.is_unspecified() => local.is_ipv4(),
+        ip => *ip == local,
+    })
+}
+
+/// 向`to`发送报文时系统按路由选择的本机地址
+fn route(to: SocketAddr) -> io::Result<IpAddr> {
+    //双栈套接字收到的IPv4来源为IPv4映射地址
+    let to = SocketAddr::new(to.ip().to_canonical(), to.port());
+    let socket = UdpSocket::bind(unspecified(&to))?;
+    socket.connect(to)?;
+    Ok(socket.local_addr()?.ip().to_canonical())
+}
+
+impl Drop for DiscoveryResponder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 局域网广播地址
+pub fn broadcast(port: u16) -> SocketAddr {
+    (Ipv4Addr::BROADCAST, port).into()
+}
+
+/// 向`targets`发送探测，收集`timeout`内回复的服务器，按回复顺序排列
+///
+/// `targets`通常为[`broadcast`]地址，也可以是单个服务器或回环地址。
+pub fn discover(targets: &[SocketAddr], timeout: Duration) -> Result<Vec<DiscoveredServer>> {
+    //每个协议族一个套接字
+    let mut sockets: Vec<UdpSocket> = vec![];
+    let probe = Protocol::discovery_request().to_vec();
+    for target in targets {
+        let socket = match sockets.iter().position(|socket| {
+            socket
+                .local_addr()
+                .is_ok_and(|local| local.is_ipv4() == target.is_ipv4())
+        }) {
+            Some(index) => &sockets[index],
+            None => {
+                let socket = UdpSocket::bind(unspecified(target))?;
+                socket.set_broadcast(target.is_ipv4())?;
+                socket.set_nonblocking(true)?;
+                sockets.push(socket);
+                &sockets[sockets.len() - 1]
+            }
+        };
+        debug!("send discovery probe to {}", target);
+        socket.send_to(&probe, target)?;
+    }
+
+    let mut servers: Vec<DiscoveredServer> = vec![];
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; PROTOCOL_MAX_LEN];
+    while Instant::now() < deadline {
+        let mut received = false;
+        for socket in &sockets {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(recv) => recv,
+                //部分平台上目标端口未打开时返回ConnectionReset/ConnectionRefused
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock
+                            | ErrorKind::ConnectionReset
+                            | ErrorKind::ConnectionRefused
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            };
+            received = true;
+            let protocol = Protocol::from(&buf[..len]);
+            if protocol.flag != Flag::DiscoveryResponse {
+                continue;
+            }
+            match protocol.announce() {
+                Ok(announce) => {
+                    let server = DiscoveredServer {
+                        name: announce.name,
+                        addr: SocketAddr::new(from.ip(), announce.port),
+                        fingerprint: announce.fingerprint,
+                    };
+                    //同一服务器可能从多个地址收到探测
+                    if !servers.contains(&server) {
+                        servers.push(server);
+                    }
+                }
+                Err(e) => warn!("invalid discovery response from {}: {}", from, e),
+            }
+        }
+        if !received {
+            thread::sleep(DISCOVERY_POLL);
+        }
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{discover, fingerprint, load_id, DiscoveryResponder};
+    use crate::net::{free_port, protocol::Announce};
+    use std::{
+        fs,
+        net::SocketAddr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    #[test]
+    fn test_discover_loopback() {
+        let announce = Announce {
+            name: "office".to_string(),
+            port: 48899,
+            fingerprint: fingerprint("office", "0123456789abcdef"),
+        };
+        let port = free_port();
+        let mut responder = DiscoveryResponder::start(
+            &["127.0.0.1:48899".parse().unwrap()],
+            port,
+            announce.clone(),
+        )
+        .unwrap();
+        //绑定未指定地址
+        assert!(responder.local_addr().unwrap().ip().is_unspecified());
+        let target: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+
+        let servers = discover(&[target, target], Duration::from_millis(300)).unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].name, "office");
+        assert_eq!(servers[0].addr, "127.0.0.1:48899".parse().unwrap());
+        assert_eq!(servers[0].fingerprint, announce.fingerprint);
+        assert_ne!(
+            announce.fingerprint,
+            fingerprint("office", "fedcba9876543210")
+        );
+
+        responder.stop();
+        let servers = discover(&[target], Duration::from_millis(100)).unwrap();
+        assert!(servers.is_empty());
+
+        //IPv6回环地址
+        let port = free_port();
+        let mut responder =
+            DiscoveryResponder::start(&["[::1]:48899".parse().unwrap()], port, announce.clone())
+                .unwrap();
+        let target: SocketAddr = format!("[::1]:{}", port).parse().unwrap();
+        let servers = discover(&[target], Duration::from_millis(300)).unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].addr, "[::1]:48899".parse().unwrap());
+        responder.stop();
+
+        //只回复能连接监听地址的探测：回环探测的回复来源为127.0.0.1，不在监听地址中
+        let port = free_port();
+        let mut responder =
+            DiscoveryResponder::start(&["127.0.0.2:48899".parse().unwrap()], port, announce)
+                .unwrap();
+        let target: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        assert!(discover(&[target], Duration::from_millis(200))
+            .unwrap()
+            .is_empty());
+        responder.stop();
+    }
+
+    #[test]
+    fn test_load_id() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("minput-id-{}", nanos));
+        let path = dir.join("server_id");
+        let id = load_id(&path).unwrap();
+        assert_eq!(id.len(), 32);
+        assert_eq!(load_id(&path).unwrap(), id);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(load_id(&path).unwrap(), id);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,14 @@
+pub mod addr;
 pub mod client;
+pub mod discovery;
 pub mod protocol;
 pub mod server;
+
+/// 获取一个空闲的本地UDP端口，用于测试
+#[cfg(test)]
+pub fn free_port() -> u16 {
+    std::net::UdpSocket::bind(("127.0.0.1", 0))
+        .and_then(|socket| socket.local_addr())
+        .map(|addr| addr.port())
+        .expect("bind udp socket")
+}
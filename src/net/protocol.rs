@@ -1,10 +1,18 @@
+use anyhow::Result;
 use log::warn;
 use rdev::{Button, EventType, Key};
+use serde::{Deserialize, Serialize};
+
+use crate::{keyboard::DEFAULT_KEYBOARD_LAYOUT, ConfigClientDirection, Display};
 
 pub const PROTOCOL_LEN: usize = 19;
+/// 报文最大长度，mtu(1500) - udp header(8) - ip header(20) = 1472，防止分片传输
+pub const PROTOCOL_MAX_LEN: usize = 1472;
+/// 滚轮滚动一格的滚动量，与Windows的WHEEL_DELTA一致，小于该值为高精度滚动
+pub const WHEEL_DELTA: i64 = 120;
 
 /// 通信协议
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Protocol {
     /// 标记
     pub flag: Flag,
@@ -12,10 +20,203 @@ pub struct Protocol {
     pub key_mouse: KeyMouse,
     /// 触发事件
     pub event: Event,
+    /// 平台原始按键编码，没有对应的按键名字时携带
+    pub raw: Option<RawCode>,
+    /// 附加数据，位于固定长度报文之后
+    pub payload: Vec<u8>,
+}
+
+/// 平台原始按键编码，只有相同平台的客户端可以模拟
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawCode {
+    /// 按键编码所属平台
+    pub platform: Platform,
+    /// 按键编码（Windows虚拟键码、X11 keycode、macOS keycode或鼠标按钮编号）
+    pub code: u32,
+}
+
+/// 客户端信息，客户端初始化连接时发送给服务端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientInfo {
+    /// 客户端名字
+    pub name: String,
+    /// 客户端屏幕
+    pub display: Display,
+    /// 客户端所在服务器显示器方向，布局中未配置该客户端时使用
+    pub direction: ConfigClientDirection,
+    /// 客户端键盘布局名字
+    #[serde(default = "default_keyboard_layout")]
+    pub keyboard_layout: String,
+}
+
+/// 服务端信息，服务端收到客户端初始化连接后回复
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// 服务器屏幕名字
+    pub name: String,
+    /// 服务器键盘布局名字，客户端按该布局转换按键
+    #[serde(default = "default_keyboard_layout")]
+    pub keyboard_layout: String,
+}
+
+/// 服务端回复发现探测的信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announce {
+    /// 服务器屏幕名字
+    pub name: String,
+    /// 服务端口
+    pub port: u16,
+    /// 服务器指纹，客户端可以据此只连接指定的服务器
+    pub fingerprint: String,
+}
+
+fn default_keyboard_layout() -> String {
+    DEFAULT_KEYBOARD_LAYOUT.to_string()
 }
 
 impl Protocol {
-    pub fn to_arr(self) -> [u8; PROTOCOL_LEN] {
+    pub fn new(flag: Flag, key_mouse: KeyMouse, event: Event) -> Self {
+        Protocol {
+            flag,
+            key_mouse,
+            event,
+            raw: None,
+            payload: vec![],
+        }
+    }
+
+    /// 客户端初始化连接报文
+    pub fn client_init(info: &ClientInfo) -> Result<Self> {
+        let mut protocol = Protocol::new(
+            Flag::ClientInitConnection,
+            KeyMouse::Unknown,
+            Event::Unknown,
+        );
+        protocol.payload = serde_json::to_vec(info)?;
+        Ok(protocol)
+    }
+
+    /// 解析客户端初始化连接报文中的客户端信息
+    pub fn client_info(&self) -> Result<ClientInfo> {
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// 服务端回复客户端初始化连接的报文
+    pub fn server_init(info: &ServerInfo) -> Result<Self> {
+        let mut protocol = Protocol::new(
+            Flag::ServerInitConnection,
+            KeyMouse::Unknown,
+            Event::Unknown,
+        );
+        protocol.payload = serde_json::to_vec(info)?;
+        Ok(protocol)
+    }
+
+    /// 解析服务端回复报文中的服务端信息
+    pub fn server_info(&self) -> Result<ServerInfo> {
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// 客户端发现探测报文
+    pub fn discovery_request() -> Self {
+        Protocol::new(Flag::DiscoveryRequest, KeyMouse::Unknown, Event::Unknown)
+    }
+
+    /// 服务端回复发现探测的报文
+    pub fn discovery_response(announce: &Announce) -> Result<Self> {
+        let mut protocol =
+            Protocol::new(Flag::DiscoveryResponse, KeyMouse::Unknown, Event::Unknown);
+        protocol.payload = serde_json::to_vec(announce)?;
+        Ok(protocol)
+    }
+
+    /// 解析发现回复报文中的服务器信息
+    pub fn announce(&self) -> Result<Announce> {
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// 心跳报文，`timestamp`为发送方的时间戳
+    pub fn heartbeat(timestamp: u64) -> Self {
+        let mut protocol = Protocol::new(Flag::Heartbeat, KeyMouse::Unknown, Event::Unknown);
+        protocol.payload = timestamp.to_be_bytes().to_vec();
+        protocol
+    }
+
+    /// 回复心跳报文
+    pub fn heartbeat_reply(&self) -> Self {
+        let mut protocol = Protocol::new(Flag::HeartbeatReply, KeyMouse::Unknown, Event::Unknown);
+        protocol.payload = self.payload.clone();
+        protocol
+    }
+
+    /// 心跳或心跳回复中的时间戳
+    pub fn heartbeat_timestamp(&self) -> Option<u64> {
+        let bytes: [u8; 8] = self.payload.get(..8)?.try_into().ok()?;
+        Some(u64::from_be_bytes(bytes))
+    }
+
+    /// 服务端断开客户端的报文
+    pub fn disconnect() -> Self {
+        Protocol::new(Flag::Disconnect, KeyMouse::Unknown, Event::Unknown)
+    }
+
+    /// 文本输入报文，客户端直接输入文本而不是模拟按键
+    pub fn text(text: &str) -> Self {
+        let mut protocol = Protocol::new(Flag::Text, KeyMouse::Unknown, Event::Unknown);
+        protocol.payload = text.as_bytes().to_vec();
+        protocol
+    }
+
+    /// 解析文本输入报文中的文本
+    pub fn to_text(&self) -> Result<String> {
+        Ok(String::from_utf8(self.payload.clone())?)
+    }
+
+    /// 转换为可模拟的鼠标键盘事件
+    pub fn to_event_type(&self) -> Option<EventType> {
+        if let Some(raw) = self.raw {
+            //原始按键编码只能在相同平台上模拟
+            if raw.platform != Platform::current() {
+                return None;
+            }
+            return match (self.key_mouse, &self.event) {
+                (KeyMouse::RawKey, Event::Press) => {
+                    Some(EventType::KeyPress(Key::Unknown(raw.code)))
+                }
+                (KeyMouse::RawKey, Event::Release) => {
+                    Some(EventType::KeyRelease(Key::Unknown(raw.code)))
+                }
+                (KeyMouse::RawButton, Event::Press) => {
+                    Some(EventType::ButtonPress(Button::Unknown(raw.code as u8)))
+                }
+                (KeyMouse::RawButton, Event::Release) => {
+                    Some(EventType::ButtonRelease(Button::Unknown(raw.code as u8)))
+                }
+                _ => None,
+            };
+        }
+        match (self.key_mouse, &self.event) {
+            (KeyMouse::MouseMove, Event::Move(x, y)) => Some(EventType::MouseMove { x: *x, y: *y }),
+            (key_mouse, Event::Press) => key_mouse
+                .to_key()
+                .map(EventType::KeyPress)
+                .or_else(|| key_mouse.to_button().map(EventType::ButtonPress)),
+            (key_mouse, Event::Release) => key_mouse
+                .to_key()
+                .map(EventType::KeyRelease)
+                .or_else(|| key_mouse.to_button().map(EventType::ButtonRelease)),
+            _ => None,
+        }
+    }
+
+    /// 转换为报文，包含附加数据
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = self.to_arr().to_vec();
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    pub fn to_arr(&self) -> [u8; PROTOCOL_LEN] {
         let mut buf = [0u8; PROTOCOL_LEN];
         let (flag, arr) = buf.split_at_mut(1);
         let (key_mouse, event) = arr.split_at_mut(1);
@@ -23,6 +224,11 @@ impl Protocol {
         key_mouse[0] = (&self.key_mouse).into();
         let e: [u8; 17] = (&self.event).into();
         event.copy_from_slice(&e);
+        //按下、释放事件的剩余字节存放原始按键编码：平台(1字节) + 编码(4字节)
+        if let (Some(raw), Event::Press | Event::Release) = (self.raw, &self.event) {
+            event[1] = (&raw.platform).into();
+            event[2..6].copy_from_slice(&raw.code.to_be_bytes());
+        }
         buf
     }
 }
@@ -33,31 +239,59 @@ impl From<rdev::Event> for Protocol {
     }
 }
 
+/// 按键按下时产生的文本，控制字符（如Ctrl+C产生的0x03）不是文本
+pub fn event_text(e: &rdev::Event) -> Option<&str> {
+    match (&e.event_type, e.name.as_deref()) {
+        (EventType::KeyPress(_), Some(name))
+            if !name.is_empty() && !name.chars().any(char::is_control) =>
+        {
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
 impl From<EventType> for Protocol {
     fn from(et: EventType) -> Self {
         //Protocol { flag: Flag::KeyMouse, key_mouse: , event: () }
+        let raw = match et {
+            EventType::KeyPress(Key::Unknown(code)) | EventType::KeyRelease(Key::Unknown(code)) => {
+                Some(code)
+            }
+            EventType::ButtonPress(Button::Unknown(code))
+            | EventType::ButtonRelease(Button::Unknown(code)) => Some(code as u32),
+            _ => None,
+        };
         let (key_mouse, event) = match et {
             EventType::KeyPress(k) => (k.into(), Event::Press),
             EventType::KeyRelease(k) => (k.into(), Event::Release),
             EventType::ButtonPress(b) => (b.into(), Event::Press),
             EventType::ButtonRelease(b) => (b.into(), Event::Release),
             EventType::MouseMove { x, y } => (KeyMouse::MouseMove, Event::Move(x, y)),
-            EventType::Wheel { delta_x, delta_y } => (
-                KeyMouse::MouseMiddle,
-                Event::Move(delta_x as f64, delta_y as f64),
+            //rdev每次只上报一个方向的滚动，以格为单位
+            EventType::Wheel { delta_x, delta_y } if delta_y == 0 && delta_x != 0 => (
+                KeyMouse::MouseHorizontalWheel,
+                Event::Wheel(delta_x * WHEEL_DELTA),
             ),
+            EventType::Wheel { delta_y, .. } => {
+                (KeyMouse::MouseWheel, Event::Wheel(delta_y * WHEEL_DELTA))
+            }
         };
-        Self {
-            flag: Flag::KeyMouse,
-            key_mouse,
-            event,
+        let mut protocol = Protocol::new(Flag::KeyMouse, key_mouse, event);
+        //附加按钮已转换为编号，不需要原始编码
+        if matches!(key_mouse, KeyMouse::RawKey | KeyMouse::RawButton) {
+            protocol.raw = raw.map(|code| RawCode {
+                platform: Platform::current(),
+                code,
+            });
         }
+        protocol
     }
 }
 
 impl From<&[u8]> for Protocol {
     fn from(buf: &[u8]) -> Self {
-        let flag = if buf.len() > 0 {
+        let flag = if !buf.is_empty() {
             Flag::from(buf[0])
         } else {
             Flag::Unknown
@@ -67,16 +301,27 @@ impl From<&[u8]> for Protocol {
         } else {
             KeyMouse::Unknown
         };
-        let event = if buf.len() == PROTOCOL_LEN {
-            //Event::from(&buf[2..])
-            Event::Unknown
+        let (event, payload) = if buf.len() >= PROTOCOL_LEN {
+            (
+                Event::from(&buf[2..PROTOCOL_LEN]),
+                buf[PROTOCOL_LEN..].to_vec(),
+            )
         } else {
-            Event::Unknown
+            (Event::Unknown, vec![])
+        };
+        let raw = match event {
+            Event::Press | Event::Release if buf[3] != 0x00 => Some(RawCode {
+                platform: Platform::from(buf[3]),
+                code: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            }),
+            _ => None,
         };
         Protocol {
             flag,
             key_mouse,
             event,
+            raw,
+            payload,
         }
     }
 }
@@ -98,19 +343,22 @@ macro_rules! from_u8 {
             }
         }
 
+        //不使用通配符，新增的值未配置编码时编译失败
         impl From<&$type> for u8 {
             fn from(value: &$type) -> Self {
                 match value {
                     $(
                         $type::$key => $code,
                     )*
-                    _ =>{
-                        warn!("unknown {} enum: {:?}", stringify!($type), value);
-                        0
-                    },
                 }
             }
         }
+
+        impl $type {
+            /// 编码表中的全部值
+            #[cfg(test)]
+            pub const ALL: &'static [$type] = &[$($type::$key),*];
+        }
     }
 }
 
@@ -123,10 +371,20 @@ macro_rules! from_key {
                     $(
                         Key::$key => $type::$key,
                     )*
-                    _ => {
-                        warn!("Unknown key: {:?}", k);
-                        KeyMouse::Unknown
-                    },
+                    //没有名字的按键携带原始按键编码
+                    Key::Unknown(_) => $type::RawKey,
+                }
+            }
+        }
+
+        impl $type {
+            /// 转换为键盘按键，非键盘按键返回None
+            pub fn to_key(self) -> Option<Key> {
+                match self {
+                    $(
+                        $type::$key => Some(Key::$key),
+                    )*
+                    _ => None,
                 }
             }
         }
@@ -134,7 +392,7 @@ macro_rules! from_key {
 }
 
 /// 标记
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Flag {
     /// 0x01键盘鼠标触发
     KeyMouse,
@@ -142,6 +400,20 @@ pub enum Flag {
     CopyPaste,
     /// 0x03客户端初始化连接
     ClientInitConnection,
+    /// 0x04文本输入，附加数据为UTF-8文本
+    Text,
+    /// 0x05服务端回复客户端初始化连接
+    ServerInitConnection,
+    /// 0x06客户端发现服务器的探测
+    DiscoveryRequest,
+    /// 0x07服务端回复发现探测，附加数据为服务器信息
+    DiscoveryResponse,
+    /// 0x08心跳，附加数据为发送方的时间戳（微秒，8字节大端）
+    Heartbeat,
+    /// 0x09心跳回复，原样带回心跳的时间戳
+    HeartbeatReply,
+    /// 0x0A断开连接：服务端发送时客户端释放按住的按键后停止，客户端停止时发送给服务端
+    Disconnect,
     /// 0x00未知数据
     Unknown,
 }
@@ -151,11 +423,38 @@ from_u8!(
     Flag,
     KeyMouse = 0x01,
     CopyPaste = 0x02,
-    ClientInitConnection = 0x03
+    ClientInitConnection = 0x03,
+    Text = 0x04,
+    ServerInitConnection = 0x05,
+    DiscoveryRequest = 0x06,
+    DiscoveryResponse = 0x07,
+    Heartbeat = 0x08,
+    HeartbeatReply = 0x09,
+    Disconnect = 0x0A,
+    Unknown = 0x00
 );
 
+impl Flag {
+    /// 统计用的名字
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::KeyMouse => "key_mouse",
+            Flag::CopyPaste => "copy_paste",
+            Flag::ClientInitConnection => "client_init",
+            Flag::Text => "text",
+            Flag::ServerInitConnection => "server_init",
+            Flag::DiscoveryRequest => "discovery_request",
+            Flag::DiscoveryResponse => "discovery_response",
+            Flag::Heartbeat => "heartbeat",
+            Flag::HeartbeatReply => "heartbeat_reply",
+            Flag::Disconnect => "disconnect",
+            Flag::Unknown => "unknown",
+        }
+    }
+}
+
 /// 鼠标键盘
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum KeyMouse {
     Alt,
     AltGr,
@@ -268,16 +567,36 @@ pub enum KeyMouse {
     MouseLeft,
     MouseRight,
     MouseMiddle,
+    /// 附加按钮1，通常为后退
+    MouseExtra1,
+    /// 附加按钮2，通常为前进
+    MouseExtra2,
+    MouseExtra3,
+    MouseExtra4,
+    MouseExtra5,
+    MouseExtra6,
+    MouseExtra7,
+    MouseExtra8,
+    /// 垂直滚轮
+    MouseWheel,
+    /// 水平滚轮
+    MouseHorizontalWheel,
+    /// 没有名字的键盘按键，编码见`Protocol::raw`
+    RawKey,
+    /// 没有名字的鼠标按钮，编号见`Protocol::raw`
+    RawButton,
     /// 0x00未知数据
     Unknown,
 }
 
-// // 映射鼠标键盘为十六进制
-// // 鼠标键盘映射表：https://docs.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes
+// 映射鼠标键盘为十六进制，编码不能重复
+// 键盘按键使用Windows虚拟键码，没有对应虚拟键码的按键使用未分配的编码
+// 鼠标键盘映射表：https://docs.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes
 from_u8!(
     KeyMouse,
     Alt = 0x12,
-    //AltGr = 0x11,
+    //AltGr的虚拟键码VK_RMENU已被MetaRight占用，使用未分配的编码
+    AltGr = 0x97,
     Backspace = 0x08,
     CapsLock = 0x14,
     ControlLeft = 0xA2,
@@ -300,6 +619,7 @@ from_u8!(
     F12 = 0x7B,
     Home = 0x24,
     LeftArrow = 0x25,
+    //沿用早期版本的编码，与旧版本互通，不是VK_LWIN、VK_RWIN
     MetaLeft = 0xA4,
     MetaRight = 0xA5,
     PageDown = 0x22,
@@ -353,22 +673,23 @@ from_u8!(
     KeyY = 0x59,
     KeyZ = 0x5A,
     Minus = 0xBD,
-    //Equal = 0x53,
-    //LeftBracket = 0x1A,
-    //RightBracket = 0x1B,
-    //SemiColon = 0x27,
-    //Quote = 0x28,
-    //BackSlash = 0x2B,
-    //IntlBackslash = 0x56,
+    Equal = 0xBB,
+    LeftBracket = 0xDB,
+    RightBracket = 0xDD,
+    SemiColon = 0xBA,
+    Quote = 0xDE,
+    BackSlash = 0xDC,
+    IntlBackslash = 0xE2,
     Comma = 0xBC,
     Dot = 0xBE,
-    //Slash = 0x35,
+    Slash = 0xBF,
     Insert = 0x2D,
-    //KpReturn = 0x4C,
-    //KpMinus = 0x4A,
-    //KpPlus = 0x4E,
-    //KpMultiply = 0x6A,
-    //KpDivide = 0x6F,
+    //小键盘回车没有虚拟键码
+    KpReturn = 0x0E,
+    KpMinus = 0x6D,
+    KpPlus = 0x6B,
+    KpMultiply = 0x6A,
+    KpDivide = 0x6F,
     Kp0 = 0x60,
     Kp1 = 0x61,
     Kp2 = 0x62,
@@ -379,12 +700,27 @@ from_u8!(
     Kp7 = 0x67,
     Kp8 = 0x68,
     Kp9 = 0x69,
-    //KpDelete = 0x53,
-    //Function = 0x3A,
+    KpDelete = 0x6E,
+    //Fn键没有虚拟键码
+    Function = 0x0F,
     MouseMove = 0x07,
     MouseLeft = 0x01,
     MouseRight = 0x02,
-    MouseMiddle = 0x04
+    MouseMiddle = 0x04,
+    //前两个附加按钮使用VK_XBUTTON1、VK_XBUTTON2，其余使用未分配的编码
+    MouseExtra1 = 0x05,
+    MouseExtra2 = 0x06,
+    MouseExtra3 = 0x8A,
+    MouseExtra4 = 0x8B,
+    MouseExtra5 = 0x8C,
+    MouseExtra6 = 0x8D,
+    MouseExtra7 = 0x8E,
+    MouseExtra8 = 0x8F,
+    MouseWheel = 0x0A,
+    MouseHorizontalWheel = 0x0B,
+    RawKey = 0x88,
+    RawButton = 0x89,
+    Unknown = 0x00
 );
 
 from_key!(
@@ -502,16 +838,131 @@ impl From<Button> for KeyMouse {
             Button::Left => KeyMouse::MouseLeft,
             Button::Right => KeyMouse::MouseRight,
             Button::Middle => KeyMouse::MouseMiddle,
-            Button::Unknown(v) => {
-                warn!("unknown button: {}", v);
-                KeyMouse::Unknown
-            }
+            Button::Unknown(code) => Platform::current()
+                .extra_button(code)
+                .and_then(KeyMouse::from_extra_button)
+                //没有名字的按钮携带原始按钮编号
+                .unwrap_or(KeyMouse::RawButton),
+        }
+    }
+}
+
+impl KeyMouse {
+    /// 统计用的分类：keyboard、button、move、wheel或unknown
+    pub fn category(self) -> &'static str {
+        match self {
+            KeyMouse::MouseMove => "move",
+            KeyMouse::MouseWheel | KeyMouse::MouseHorizontalWheel => "wheel",
+            KeyMouse::RawButton => "button",
+            KeyMouse::Unknown => "unknown",
+            _ if self.to_button().is_some() => "button",
+            _ => "keyboard",
+        }
+    }
+
+    /// 转换为鼠标按钮，非鼠标按钮返回None
+    pub fn to_button(self) -> Option<Button> {
+        match self {
+            KeyMouse::MouseLeft => Some(Button::Left),
+            KeyMouse::MouseRight => Some(Button::Right),
+            KeyMouse::MouseMiddle => Some(Button::Middle),
+            _ => self
+                .extra_button()
+                .and_then(|n| Platform::current().extra_button_code(n))
+                .map(Button::Unknown),
+        }
+    }
+
+    /// 附加按钮的编号，从1开始，非附加按钮返回None
+    pub fn extra_button(self) -> Option<u8> {
+        EXTRA_BUTTONS
+            .iter()
+            .position(|b| *b == self)
+            .map(|i| i as u8 + 1)
+    }
+
+    /// 编号对应的附加按钮
+    pub fn from_extra_button(n: u8) -> Option<Self> {
+        EXTRA_BUTTONS.get((n as usize).checked_sub(1)?).copied()
+    }
+}
+
+/// 鼠标附加按钮，按编号排列
+const EXTRA_BUTTONS: [KeyMouse; 8] = [
+    KeyMouse::MouseExtra1,
+    KeyMouse::MouseExtra2,
+    KeyMouse::MouseExtra3,
+    KeyMouse::MouseExtra4,
+    KeyMouse::MouseExtra5,
+    KeyMouse::MouseExtra6,
+    KeyMouse::MouseExtra7,
+    KeyMouse::MouseExtra8,
+];
+
+/// 平台
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// 0x01 Windows
+    Windows,
+    /// 0x02 Linux(X11)
+    Linux,
+    /// 0x03 macOS
+    MacOS,
+    /// 0x00未知平台
+    Unknown,
+}
+
+from_u8!(
+    Platform,
+    Windows = 0x01,
+    Linux = 0x02,
+    MacOS = 0x03,
+    Unknown = 0x00
+);
+
+impl Platform {
+    /// 当前运行的平台
+    pub fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            Platform::Windows
+        } else if cfg!(target_os = "linux") {
+            Platform::Linux
+        } else if cfg!(target_os = "macos") {
+            Platform::MacOS
+        } else {
+            Platform::Unknown
+        }
+    }
+
+    /// 平台按钮编号转换为附加按钮编号
+    ///
+    /// Windows的XBUTTON1、XBUTTON2为1、2；X11的8、9为后退、前进，之后依次为其他附加按钮；
+    /// macOS的按钮编号从0开始，3、4为后退、前进。
+    pub fn extra_button(self, code: u8) -> Option<u8> {
+        let (first, last) = self.extra_button_range()?;
+        (first..=last).contains(&code).then(|| code - first + 1)
+    }
+
+    /// 附加按钮编号转换为平台按钮编号
+    pub fn extra_button_code(self, n: u8) -> Option<u8> {
+        let (first, last) = self.extra_button_range()?;
+        let code = first.checked_add(n.checked_sub(1)?)?;
+        (code <= last).then_some(code)
+    }
+
+    /// 平台附加按钮编号的范围
+    fn extra_button_range(self) -> Option<(u8, u8)> {
+        match self {
+            Platform::Windows => Some((1, 2)),
+            Platform::Linux => Some((8, 15)),
+            Platform::MacOS => Some((3, 10)),
+            Platform::Unknown => None,
         }
     }
 }
 
 /// 鼠标键盘事件
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     /// 0x01按下按钮事件
     Press,
@@ -520,22 +971,39 @@ pub enum Event {
     /// 0x03移动事件
     /// 鼠标、滚轮x轴、y轴偏移
     Move(f64, f64),
+    /// 0x04相对移动事件
+    /// 鼠标x轴、y轴移动偏移量
+    RelativeMove(f64, f64),
+    /// 0x05滚轮事件
+    /// 高精度滚动量，`WHEEL_DELTA`为一格，正数向上/向右
+    Wheel(i64),
     /// 0x00未知数据
     Unknown,
 }
 
 impl From<&[u8]> for Event {
     fn from(v: &[u8]) -> Self {
-        if v.len() > 0 {
+        if !v.is_empty() {
             match v[0] {
                 0x01 => Event::Press,
                 0x02 => Event::Release,
-                0x03 => {
+                0x03 | 0x04 => {
                     if v.len() == PROTOCOL_LEN - 2 {
                         //转换为x、y轴偏移数据
-                        let x = f64::from_be_bytes(v[1..8].try_into().unwrap_or_default());
-                        let y = f64::from_be_bytes(v[9..16].try_into().unwrap_or_default());
-                        Event::Move(x, y)
+                        let x = f64::from_be_bytes(v[1..9].try_into().unwrap_or_default());
+                        let y = f64::from_be_bytes(v[9..17].try_into().unwrap_or_default());
+                        if v[0] == 0x03 {
+                            Event::Move(x, y)
+                        } else {
+                            Event::RelativeMove(x, y)
+                        }
+                    } else {
+                        Event::Unknown
+                    }
+                }
+                0x05 => {
+                    if v.len() == PROTOCOL_LEN - 2 {
+                        Event::Wheel(i64::from_be_bytes(v[1..9].try_into().unwrap_or_default()))
                     } else {
                         Event::Unknown
                     }
@@ -562,6 +1030,15 @@ impl From<&Event> for [u8; 17] {
                 x.copy_from_slice(&xf.to_be_bytes());
                 y.copy_from_slice(&yf.to_be_bytes());
             }
+            Event::RelativeMove(xf, yf) => {
+                flag[0] = 0x04;
+                x.copy_from_slice(&xf.to_be_bytes());
+                y.copy_from_slice(&yf.to_be_bytes());
+            }
+            Event::Wheel(delta) => {
+                flag[0] = 0x05;
+                x.copy_from_slice(&delta.to_be_bytes());
+            }
             Event::Unknown => flag[0] = 0x00,
         };
         buf
@@ -570,7 +1047,11 @@ impl From<&Event> for [u8; 17] {
 
 #[cfg(test)]
 mod test {
-    use super::{Event, Flag, KeyMouse, Protocol, PROTOCOL_LEN};
+    use super::{
+        event_text, Event, Flag, KeyMouse, Platform, Protocol, RawCode, PROTOCOL_LEN, WHEEL_DELTA,
+    };
+    use rdev::{Button, EventType, Key};
+    use std::collections::HashSet;
 
     #[test]
     fn test_protocol_to_u8() {
@@ -578,6 +1059,8 @@ mod test {
             flag: Flag::KeyMouse,
             key_mouse: KeyMouse::MouseMove,
             event: Event::Move(0.1, 0.1),
+            raw: None,
+            payload: vec![],
         };
         let buf: [u8; PROTOCOL_LEN] = p.to_arr();
         assert_eq!(
@@ -588,4 +1071,136 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_relative_move_round_trip() {
+        let p = Protocol::new(
+            Flag::KeyMouse,
+            KeyMouse::MouseMove,
+            Event::RelativeMove(-3.0, 12.5),
+        );
+        let decoded = Protocol::from(&p.to_vec()[..]);
+        assert_eq!(decoded.flag, Flag::KeyMouse);
+        assert_eq!(decoded.key_mouse, KeyMouse::MouseMove);
+        assert_eq!(decoded.event, Event::RelativeMove(-3.0, 12.5));
+    }
+
+    #[test]
+    fn test_wheel_is_not_middle_button() {
+        let p = Protocol::from(EventType::Wheel {
+            delta_x: 0,
+            delta_y: -2,
+        });
+        assert_eq!(p.key_mouse, KeyMouse::MouseWheel);
+        assert_eq!(p.event, Event::Wheel(-2 * WHEEL_DELTA));
+
+        let p = Protocol::from(&p.to_vec()[..]);
+        assert_eq!(p.key_mouse, KeyMouse::MouseWheel);
+        assert_eq!(p.event, Event::Wheel(-240));
+
+        let p = Protocol::from(EventType::Wheel {
+            delta_x: 1,
+            delta_y: 0,
+        });
+        assert_eq!(p.key_mouse, KeyMouse::MouseHorizontalWheel);
+        assert_eq!(p.event, Event::Wheel(WHEEL_DELTA));
+    }
+
+    #[test]
+    fn test_key_mouse_round_trip() {
+        let mut codes = HashSet::new();
+        for key_mouse in KeyMouse::ALL {
+            let code = u8::from(key_mouse);
+            assert!(codes.insert(code), "duplicate code {:#04X}", code);
+            assert_eq!(KeyMouse::from(code), *key_mouse);
+        }
+        //已发布的编码不能改变
+        assert_eq!(u8::from(&KeyMouse::MetaLeft), 0xA4);
+        assert_eq!(u8::from(&KeyMouse::MetaRight), 0xA5);
+    }
+
+    #[test]
+    fn test_flag_round_trip() {
+        for flag in Flag::ALL {
+            assert_eq!(Flag::from(u8::from(flag)), *flag);
+        }
+    }
+
+    #[test]
+    fn test_raw_key_code() {
+        let p = Protocol::from(EventType::KeyPress(Key::Unknown(0xAD)));
+        assert_eq!(p.key_mouse, KeyMouse::RawKey);
+        let p = Protocol::from(&p.to_vec()[..]);
+        assert_eq!(p.event, Event::Press);
+        assert_eq!(
+            p.raw,
+            Some(RawCode {
+                platform: Platform::current(),
+                code: 0xAD
+            })
+        );
+        assert_eq!(
+            p.to_event_type(),
+            Some(EventType::KeyPress(Key::Unknown(0xAD)))
+        );
+
+        //其他平台的原始按键编码不能模拟
+        let mut p = Protocol::from(EventType::ButtonRelease(Button::Unknown(200)));
+        p.raw = Some(RawCode {
+            platform: Platform::Unknown,
+            code: 200,
+        });
+        assert_eq!(p.key_mouse, KeyMouse::RawButton);
+        assert_eq!(p.to_event_type(), None);
+    }
+
+    #[test]
+    fn test_extra_button() {
+        assert_eq!(Platform::Windows.extra_button(1), Some(1));
+        assert_eq!(Platform::Windows.extra_button(3), None);
+        assert_eq!(Platform::Linux.extra_button(9), Some(2));
+        assert_eq!(Platform::Linux.extra_button(6), None);
+        assert_eq!(Platform::MacOS.extra_button_code(1), Some(3));
+        assert_eq!(Platform::Linux.extra_button_code(8), Some(15));
+        assert_eq!(Platform::Linux.extra_button_code(9), None);
+        assert_eq!(Platform::Windows.extra_button_code(0), None);
+
+        for n in 1..=8 {
+            let key_mouse = KeyMouse::from_extra_button(n).unwrap();
+            assert_eq!(key_mouse.extra_button(), Some(n));
+        }
+        assert_eq!(KeyMouse::from_extra_button(0), None);
+        assert_eq!(KeyMouse::from_extra_button(9), None);
+
+        //当前平台的后退按钮转换为附加按钮1，不携带原始编码
+        if let Some(code) = Platform::current().extra_button_code(1) {
+            let p = Protocol::from(EventType::ButtonPress(Button::Unknown(code)));
+            assert_eq!(p.key_mouse, KeyMouse::MouseExtra1);
+            assert_eq!(p.raw, None);
+            let p = Protocol::from(&p.to_vec()[..]);
+            assert_eq!(
+                p.to_event_type(),
+                Some(EventType::ButtonPress(Button::Unknown(code)))
+            );
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let p = Protocol::from(&Protocol::text("你好, Grüße").to_vec()[..]);
+        assert_eq!(p.flag, Flag::Text);
+        assert_eq!(p.to_text().unwrap(), "你好, Grüße");
+
+        let event = |event_type, name: &str| rdev::Event {
+            time: std::time::SystemTime::now(),
+            name: Some(name.to_string()),
+            event_type,
+        };
+        let a = event(EventType::KeyPress(Key::KeyA), "a");
+        assert_eq!(event_text(&a), Some("a"));
+        let ctrl_c = event(EventType::KeyPress(Key::KeyC), "\u{3}");
+        assert_eq!(event_text(&ctrl_c), None);
+        let release = event(EventType::KeyRelease(Key::KeyA), "a");
+        assert_eq!(event_text(&release), None);
+    }
 }
@@ -1,98 +1,460 @@
 use anyhow::Result;
-use lazy_static::lazy_static;
 use log::{debug, error, info, warn};
-use rdev::{Event, EventType};
 use std::{
+    collections::HashMap,
+    io::ErrorKind,
     net::{SocketAddr, UdpSocket},
-    sync::{mpsc::Receiver, Arc, RwLock},
-    thread, vec,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use crate::{net::protocol::Flag, DISPLAY};
+use crate::{
+    metrics::{Direction, Metrics},
+    net::{addr::bind_all, protocol::Flag},
+    status::{Notifier, Status},
+};
+
+use super::protocol::{ClientInfo, Protocol, ServerInfo, PROTOCOL_MAX_LEN};
 
-use super::protocol::{Protocol, PROTOCOL_LEN};
+/// 工作线程检查停止标记的间隔
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 发送心跳的间隔，用于统计往返时间
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// 超过该时间未收到客户端报文（心跳回复）时移除客户端
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(3);
 
-lazy_static! {
-    pub(crate) static ref ACTIVE_CLIENT: RwLock<Option<SocketAddr>> = RwLock::new(None);
-    static ref CLIENTS: RwLock<Vec<SocketAddr>> = RwLock::new(vec![]);
+/// 已连接的客户端
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// 客户端地址
+    pub addr: SocketAddr,
+    /// 客户端信息
+    pub info: ClientInfo,
+    /// 最后一次收到客户端报文的时间
+    pub last_seen: Instant,
 }
 
-pub struct UdpServer {
-    socket: Arc<UdpSocket>,
+/// 服务端共享状态，网络线程与鼠标键盘监听共同访问
+#[derive(Debug, Default)]
+pub struct ServerState {
+    /// 当前激活的客户端
+    pub active_client: RwLock<Option<SocketAddr>>,
+    /// 已连接的客户端
+    pub clients: RwLock<Vec<Client>>,
+    /// 光标锁定在当前屏幕，由热键或控制接口切换
+    pub cursor_locked: AtomicBool,
+    /// 状态回调
+    pub notifier: Notifier,
+    /// 运行指标
+    pub metrics: Arc<Metrics>,
 }
 
-impl UdpServer {
-    pub fn new(ip: &str, port: u16) -> Result<Self> {
-        let socket = UdpSocket::bind((ip, port))?;
-        debug!("UdpSocket bind to {}:{}", ip, port);
-        Ok(Self {
-            socket: Arc::new(socket),
-        })
+impl ServerState {
+    /// 添加客户端，同名客户端重新连接时更新地址
+    fn add_client(&self, addr: SocketAddr, info: ClientInfo) {
+        debug!("client connect: {} {:?}", addr, info);
+        if let Ok(mut clients) = self.clients.write() {
+            if let Some(client) = clients.iter_mut().find(|c| c.info.name == info.name) {
+                if client.addr != addr {
+                    warn!("client {} reconnect from {}", info.name, addr);
+                    self.replace_active(client.addr, addr);
+                }
+                client.addr = addr;
+                client.info = info.clone();
+                client.last_seen = Instant::now();
+            } else {
+                info!("add client {} [{}] success", info.name, addr);
+                clients.push(Client {
+                    addr,
+                    info: info.clone(),
+                    last_seen: Instant::now(),
+                });
+            }
+            drop(clients);
+            self.notifier.notify(Status::ClientConnected {
+                name: info.name,
+                addr,
+            });
+        } else {
+            error!("clients write error");
+        }
     }
 
-    pub fn send(&self, protocol: Protocol) -> Result<()> {
-        //if let Ok(client) = ACTIVE_CLIENT.read() {
-        //    if client.is_some() {
-        //        self.socket.send_to(&protocol.to_arr(), client.unwrap())?;
-        //    }
-        //}
-        Ok(())
+    /// 移除客户端，返回是否为当前激活的客户端，客户端不存在时返回None
+    pub fn remove_client(&self, name: &str) -> Option<bool> {
+        self.remove(|c| c.info.name == name)
+            .pop()
+            .map(|(_, active)| active)
     }
-}
 
-pub fn start(ip: &str, port: u16, rx: Receiver<Event>) -> Result<()> {
-    let udp = Arc::new(UdpServer::new(ip, port)?);
-    let udp_clone = udp.clone();
-    thread::spawn(move || {
-        for event in rx.iter() {
-            match event.event_type {
-                EventType::MouseMove { x, y } => {
-                    //active_client(x, y);
-                }
-                _ => {}
+    /// 客户端主动断开，按地址移除
+    fn disconnect(&self, addr: SocketAddr) {
+        for (client, _) in self.remove(|c| c.addr == addr) {
+            info!("client {} disconnected", client.info.name);
+        }
+    }
+
+    /// 收到已连接客户端的报文，更新最后收到报文的时间
+    fn touch(&self, addr: SocketAddr) {
+        if let Ok(mut clients) = self.clients.write() {
+            if let Some(client) = clients.iter_mut().find(|c| c.addr == addr) {
+                client.last_seen = Instant::now();
             }
-            let result = udp.send(event.into());
-            if result.is_err() {
-                error!("send event error: {:?}", result);
+        }
+    }
+
+    /// 移除超过`timeout`未收到报文的客户端，返回移除的客户端地址
+    fn expire(&self, timeout: Duration) -> Vec<SocketAddr> {
+        self.remove(|c| c.last_seen.elapsed() >= timeout)
+            .into_iter()
+            .map(|(client, _)| {
+                warn!("client {} [{}] timed out", client.info.name, client.addr);
+                client.addr
+            })
+            .collect()
+    }
+
+    /// 移除满足条件的客户端，清除激活的客户端并通知，返回移除的客户端及是否为激活的客户端
+    ///
+    /// 激活的客户端被移除后，服务器光标由事件处理器定时检查时回到服务器屏幕。
+    fn remove<F: Fn(&Client) -> bool>(&self, f: F) -> Vec<(Client, bool)> {
+        let removed: Vec<Client> = match self.clients.write() {
+            Ok(mut clients) => {
+                let (removed, kept) = clients.drain(..).partition(|c| f(c));
+                *clients = kept;
+                removed
+            }
+            Err(e) => {
+                error!("clients write error: {}", e);
+                return vec![];
+            }
+        };
+        removed
+            .into_iter()
+            .map(|client| {
+                info!("remove client {} [{}]", client.info.name, client.addr);
+                let active = self.active_client.write().is_ok_and(|mut active| {
+                    let matched = *active == Some(client.addr);
+                    if matched {
+                        *active = None;
+                    }
+                    matched
+                });
+                self.notifier.notify(Status::ClientDisconnected {
+                    name: client.info.name.clone(),
+                    addr: client.addr,
+                });
+                (client, active)
+            })
+            .collect()
+    }
+
+    /// 客户端地址变化时，同步更新激活的客户端
+    fn replace_active(&self, old: SocketAddr, new: SocketAddr) {
+        if let Ok(mut active) = self.active_client.write() {
+            if *active == Some(old) {
+                *active = Some(new);
             }
         }
-    });
-    thread::spawn(move || loop {
-        // max 1472 bytes, mtu(1500) - udp header(8) - ip header(20) = 1472
-        //每次传输报文控制在最大1472字节，防止分片传输
-        //每次接收512字节，最长不超过512
-        let mut buf = [0u8; PROTOCOL_LEN];
-        //let recv = udp_clone.socket.recv_from(&mut buf);
-        let recv = udp_clone.socket.recv_from(&mut buf);
-        if let Ok((_, addr)) = recv {
-            let protocol = Protocol::from(&buf[..]);
-            debug!(
-                "recv from {:?}, {:?}, {:?}",
-                addr.ip(),
-                addr.port(),
-                protocol
-            );
-            match protocol.flag {
-                Flag::ClientInitConnection => {
-                    debug!("client connect: {}", addr);
-                    if let Ok(mut clients) = CLIENTS.write() {
-                        if clients.contains(&addr) {
-                            warn!("client {} already exist", addr);
-                        } else {
-                            debug!("add {} client success", addr);
-                            clients.push(addr);
+    }
+}
+
+/// 向指定客户端发送报文，使用客户端连接时的套接字
+#[derive(Clone)]
+pub struct ClientSender {
+    sockets: Vec<Arc<UdpSocket>>,
+    routes: Arc<RwLock<HashMap<SocketAddr, usize>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl ClientSender {
+    /// 直接发送给指定客户端，不经过激活的客户端
+    pub fn send(&self, addr: SocketAddr, protocol: &Protocol) -> Result<()> {
+        send_to(&self.sockets, &self.routes, addr, protocol, &self.metrics)
+    }
+
+    /// 通知客户端断开，客户端释放按住的按键后停止
+    pub fn disconnect(&self, addr: SocketAddr) -> Result<()> {
+        let result = send_to(
+            &self.sockets,
+            &self.routes,
+            addr,
+            &Protocol::disconnect(),
+            &self.metrics,
+        );
+        if let Ok(mut routes) = self.routes.write() {
+            routes.remove(&addr);
+        }
+        result
+    }
+}
+
+/// UDP服务端，每个监听地址一个套接字，持有发送、接收线程
+pub struct UdpServer {
+    sockets: Vec<Arc<UdpSocket>>,
+    routes: Arc<RwLock<HashMap<SocketAddr, usize>>>,
+    metrics: Arc<Metrics>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl UdpServer {
+    /// 绑定所有监听地址并启动发送、接收线程，`rx`中的报文发送到当前激活的客户端
+    pub fn start(
+        hosts: &[String],
+        port: u16,
+        info: ServerInfo,
+        state: Arc<ServerState>,
+        rx: Receiver<Protocol>,
+    ) -> Result<Self> {
+        let mut sockets = vec![];
+        for socket in bind_all(hosts, port)? {
+            socket.set_read_timeout(Some(POLL_INTERVAL))?;
+            debug!("UdpSocket bind to {}", socket.local_addr()?);
+            sockets.push(Arc::new(socket));
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        //客户端连接时使用的套接字序号，发送时使用同一套接字，保证来源地址与客户端连接的地址相同
+        let routes: Arc<RwLock<HashMap<SocketAddr, usize>>> = Arc::default();
+
+        let sender = {
+            let (sockets, routes, state, stop) =
+                (sockets.clone(), routes.clone(), state.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut heartbeat = Instant::now();
+                while !stop.load(Ordering::Relaxed) {
+                    if heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                        heartbeat = Instant::now();
+                        for addr in state.expire(CLIENT_TIMEOUT) {
+                            if let Ok(mut routes) = routes.write() {
+                                routes.remove(&addr);
+                            }
+                        }
+                        send_heartbeat(&sockets, &routes, &state);
+                    }
+                    match rx.recv_timeout(POLL_INTERVAL) {
+                        Ok(protocol) => {
+                            if let Err(e) = send_active(&sockets, &routes, &state, protocol) {
+                                error!("send event error: {:?}", e);
+                            }
                         }
-                    } else {
-                        error!("clients write error");
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
                     }
                 }
-                _ => {
-                    warn!("unknown protocol: {:?}", protocol);
+            })
+        };
+        let mut threads = vec![sender];
+        for (index, socket) in sockets.iter().enumerate() {
+            let (socket, routes, state, stop, server_init) = (
+                socket.clone(),
+                routes.clone(),
+                state.clone(),
+                stop.clone(),
+                Protocol::server_init(&info)?,
+            );
+            threads.push(thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    // max 1472 bytes, mtu(1500) - udp header(8) - ip header(20) = 1472
+                    //每次传输报文控制在最大1472字节，防止分片传输
+                    let mut buf = [0u8; PROTOCOL_MAX_LEN];
+                    let (len, addr) = match socket.recv_from(&mut buf) {
+                        Ok(recv) => recv,
+                        Err(e)
+                            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                        {
+                            continue
+                        }
+                        Err(e) => {
+                            warn!("接收数据错误: {:?}", e);
+                            continue;
+                        }
+                    };
+                    let protocol = Protocol::from(&buf[..len]);
+                    debug!(
+                        "recv from {:?}, {:?}, {:?}",
+                        addr.ip(),
+                        addr.port(),
+                        protocol
+                    );
+                    let metrics = &state.metrics;
+                    metrics.count(Direction::Received, &protocol);
+                    match protocol.flag {
+                        Flag::ClientInitConnection => match protocol.client_info() {
+                            Ok(info) => {
+                                if let Ok(mut routes) = routes.write() {
+                                    routes.insert(addr, index);
+                                }
+                                state.add_client(addr, info);
+                                reply(&socket, &server_init, addr, metrics);
+                            }
+                            Err(e) => warn!("client {} info error: {}", addr, e),
+                        },
+                        //不回复未连接的客户端，服务端重启后客户端收不到回复时重新发送连接请求
+                        Flag::Heartbeat if is_routed(&routes, addr) => {
+                            state.touch(addr);
+                            reply(&socket, &protocol.heartbeat_reply(), addr, metrics)
+                        }
+                        Flag::Heartbeat => debug!("heartbeat from unknown client {}", addr),
+                        Flag::HeartbeatReply => {
+                            state.touch(addr);
+                            match protocol.heartbeat_timestamp() {
+                                Some(timestamp) => metrics.observe_rtt(timestamp),
+                                None => warn!("invalid heartbeat reply from {}", addr),
+                            }
+                        }
+                        Flag::Disconnect => {
+                            if let Ok(mut routes) = routes.write() {
+                                routes.remove(&addr);
+                            }
+                            state.disconnect(addr);
+                        }
+                        _ => {
+                            warn!("unknown protocol: {:?}", protocol);
+                        }
+                    }
                 }
+            }));
+        }
+
+        Ok(Self {
+            sockets,
+            routes,
+            metrics: state.metrics.clone(),
+            stop,
+            threads,
+        })
+    }
+
+    /// 第一个监听地址实际绑定的地址，端口为0时由系统分配
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.sockets[0].local_addr()?)
+    }
+
+    /// 所有监听地址
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.sockets
+            .iter()
+            .filter_map(|socket| socket.local_addr().ok())
+            .collect()
+    }
+
+    /// 向指定客户端发送报文的句柄
+    pub fn sender(&self) -> ClientSender {
+        ClientSender {
+            sockets: self.sockets.clone(),
+            routes: self.routes.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// 停止并等待发送、接收线程退出
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("udp server thread panicked");
             }
-        } else {
-            warn!("接收数据错误: {:?}", recv);
         }
-    });
-    Ok(())
+    }
+}
+
+impl Drop for UdpServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 客户端是否已连接
+fn is_routed(routes: &RwLock<HashMap<SocketAddr, usize>>, addr: SocketAddr) -> bool {
+    routes.read().is_ok_and(|routes| routes.contains_key(&addr))
+}
+
+/// 回复客户端
+fn reply(socket: &UdpSocket, protocol: &Protocol, addr: SocketAddr, metrics: &Metrics) {
+    match socket.send_to(&protocol.to_vec(), addr) {
+        Ok(_) => metrics.count(Direction::Sent, protocol),
+        Err(e) => {
+            warn!("reply client {} error: {}", addr, e);
+            metrics.count(Direction::Dropped, protocol);
+        }
+    }
+}
+
+/// 发送到当前激活的客户端，没有激活的客户端时丢弃
+fn send_active(
+    sockets: &[Arc<UdpSocket>],
+    routes: &RwLock<HashMap<SocketAddr, usize>>,
+    state: &ServerState,
+    protocol: Protocol,
+) -> Result<()> {
+    let active = state.active_client.read().ok().and_then(|client| *client);
+    match active {
+        Some(addr) => send_to(sockets, routes, addr, &protocol, &state.metrics),
+        None => {
+            state.metrics.count(Direction::Dropped, &protocol);
+            Ok(())
+        }
+    }
+}
+
+/// 向所有已连接的客户端发送心跳
+fn send_heartbeat(
+    sockets: &[Arc<UdpSocket>],
+    routes: &RwLock<HashMap<SocketAddr, usize>>,
+    state: &ServerState,
+) {
+    let addrs: Vec<SocketAddr> = state
+        .clients
+        .read()
+        .map(|clients| clients.iter().map(|c| c.addr).collect())
+        .unwrap_or_default();
+    let heartbeat = Protocol::heartbeat(state.metrics.timestamp());
+    for addr in addrs {
+        if let Err(e) = send_to(sockets, routes, addr, &heartbeat, &state.metrics) {
+            debug!("send heartbeat to {} error: {}", addr, e);
+        }
+    }
+}
+
+/// 使用客户端连接时的套接字发送
+fn send_to(
+    sockets: &[Arc<UdpSocket>],
+    routes: &RwLock<HashMap<SocketAddr, usize>>,
+    addr: SocketAddr,
+    protocol: &Protocol,
+    metrics: &Metrics,
+) -> Result<()> {
+    let index = routes
+        .read()
+        .ok()
+        .and_then(|routes| routes.get(&addr).copied());
+    //未记录的客户端使用同一协议族的套接字
+    let socket = match index {
+        Some(index) => sockets.get(index),
+        None => sockets.iter().find(|socket| {
+            socket
+                .local_addr()
+                .is_ok_and(|local| local.is_ipv4() == addr.is_ipv4())
+        }),
+    };
+    let Some(socket) = socket else {
+        metrics.count(Direction::Dropped, protocol);
+        return Ok(());
+    };
+    match socket.send_to(&protocol.to_vec(), addr) {
+        Ok(_) => {
+            metrics.count(Direction::Sent, protocol);
+            Ok(())
+        }
+        Err(e) => {
+            metrics.count(Direction::Dropped, protocol);
+            Err(e.into())
+        }
+    }
 }
@@ -0,0 +1,592 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde_yaml::{Mapping, Value};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{validate::check_duplicate_keys, Config, ConfigErrors};
+
+/// 环境变量前缀，如`MINPUT_CLIENT_NAME`对应`client.name`
+pub const ENV_PREFIX: &str = "MINPUT_";
+/// 默认的本地配置文件
+pub const LOCAL_CONFIG: &str = "config.yaml";
+/// 系统、用户配置文件所在目录名
+const APP_DIR: &str = "minput-mirror";
+
+/// 可以通过环境变量设置的字段，值为true时按字符串处理，否则按YAML解析（数字、布尔值等）
+const ENV_KEYS: &[(&str, bool)] = &[
+    ("mode", true),
+    ("keyboard_layout", true),
+    ("metrics_port", false),
+    ("server.name", true),
+    ("server.ip", true),
+    ("server.port", false),
+    ("server.lock_key", true),
+    ("server.edge_guard.dwell", false),
+    ("server.edge_guard.double_tap", false),
+    ("server.edge_guard.double_tap_interval", false),
+    ("server.edge_guard.corner", false),
+    ("server.edge_guard.drag", false),
+    ("server.discovery", false),
+    ("server.discovery_port", false),
+    ("server.control_socket", true),
+    ("client.name", true),
+    ("client.server_ip", true),
+    ("client.server_port", false),
+    ("client.direction", true),
+    ("client.server_fingerprint", true),
+    ("client.discovery_port", false),
+];
+
+/// 配置值的来源
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// 系统配置文件
+    System(PathBuf),
+    /// 用户配置文件
+    User(PathBuf),
+    /// 本地配置文件（当前目录或--config指定）
+    Local(PathBuf),
+    /// 环境变量
+    Env(String),
+    /// 命令行参数
+    Args,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::System(path) => write!(f, "system {}", path.display()),
+            ConfigSource::User(path) => write!(f, "user {}", path.display()),
+            ConfigSource::Local(path) => write!(f, "local {}", path.display()),
+            ConfigSource::Env(name) => write!(f, "env {}", name),
+            ConfigSource::Args => write!(f, "command line"),
+        }
+    }
+}
+
+/// 配置文件层
+#[derive(Debug, Clone)]
+struct FileLayer {
+    source: ConfigSource,
+    path: PathBuf,
+    /// 文件不存在时是否报错
+    required: bool,
+}
+
+/// 分层配置：系统文件、用户文件、本地文件、`MINPUT_*`环境变量、命令行参数依次合并，
+/// 后面的来源覆盖前面的同名字段，映射逐层合并，列表整体替换
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    files: Vec<FileLayer>,
+    env: Vec<(String, String)>,
+    args: Vec<(String, Value)>,
+}
+
+impl ConfigSources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 标准来源：系统文件、用户文件、本地文件和当前进程的环境变量
+    ///
+    /// `local`为None时使用当前目录下的config.yaml，不存在时跳过；指定的文件必须存在。
+    pub fn standard(local: Option<&Path>) -> Self {
+        let vars: Vec<(String, String)> = env::vars().collect();
+        let mut sources = Self::new();
+        if let Some(path) = system_path(&vars) {
+            sources = sources.system(path);
+        }
+        if let Some(path) = user_path(&vars) {
+            sources = sources.user(path);
+        }
+        match local {
+            Some(path) => sources.local(path, true),
+            None => sources.local(LOCAL_CONFIG, false),
+        }
+        .env(vars)
+    }
+
+    /// 系统配置文件，不存在时跳过
+    pub fn system<P: Into<PathBuf>>(self, path: P) -> Self {
+        let path = path.into();
+        self.file(ConfigSource::System(path.clone()), path, false)
+    }
+
+    /// 用户配置文件，不存在时跳过
+    pub fn user<P: Into<PathBuf>>(self, path: P) -> Self {
+        let path = path.into();
+        self.file(ConfigSource::User(path.clone()), path, false)
+    }
+
+    /// 本地配置文件，`required`为true时不存在报错
+    pub fn local<P: Into<PathBuf>>(self, path: P, required: bool) -> Self {
+        let path = path.into();
+        self.file(ConfigSource::Local(path.clone()), path, required)
+    }
+
+    fn file(mut self, source: ConfigSource, path: PathBuf, required: bool) -> Self {
+        self.files.push(FileLayer {
+            source,
+            path,
+            required,
+        });
+        self
+    }
+
+    /// 环境变量，只使用`MINPUT_`开头的变量
+    pub fn env<I>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut vars: Vec<_> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        //按变量名排序，结果与环境变量的顺序无关
+        vars.sort();
+        self.env.extend(vars);
+        self
+    }
+
+    /// 命令行参数，`path`为字段路径，如`client.server_ip`
+    pub fn arg<V: Into<Value>>(mut self, path: &str, value: V) -> Self {
+        self.args.push((path.to_string(), value.into()));
+        self
+    }
+
+    /// 所有配置文件，用于监视修改
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.iter().map(|file| file.path.clone()).collect()
+    }
+
+    /// 按顺序合并所有来源
+    pub fn load(&self) -> Result<LayeredConfig> {
+        let mut layered = Layers::default();
+        let mut found = false;
+        for file in &self.files {
+            let text = match fs::read_to_string(&file.path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && !file.required => continue,
+                Err(e) => return Err(anyhow!("读取配置文件{}失败: {}", file.path.display(), e)),
+            };
+            found = true;
+            check_duplicate_keys(&text, &file.source)?;
+            let value: Value = serde_yaml::from_str(&text)
+                .map_err(|e| anyhow!("配置文件{}解析失败: {}", file.path.display(), e))?;
+            //空文件解析为Null，不覆盖任何字段
+            if !value.is_null() {
+                layered.merge(&[], value, &file.source);
+            }
+        }
+
+        let mut env_used = false;
+        for (name, value) in &self.env {
+            match env_key(name) {
+                Some((path, string)) => {
+                    let value = if string {
+                        Value::String(value.clone())
+                    } else {
+                        serde_yaml::from_str(value)
+                            .map_err(|e| anyhow!("环境变量{}解析失败: {}", name, e))?
+                    };
+                    let path: Vec<&str> = path.split('.').collect();
+                    layered.set(&path, value, &ConfigSource::Env(name.clone()));
+                    env_used = true;
+                }
+                None => warn!("未知的环境变量{}", name),
+            }
+        }
+        for (path, value) in &self.args {
+            let path: Vec<&str> = path.split('.').collect();
+            layered.set(&path, value.clone(), &ConfigSource::Args);
+        }
+
+        if !found && !env_used && self.args.is_empty() {
+            let files: Vec<_> = self
+                .files
+                .iter()
+                .map(|f| f.path.display().to_string())
+                .collect();
+            return Err(anyhow!("没有找到配置文件: {}", files.join(", ")));
+        }
+        let config: Config =
+            serde_yaml::from_value(layered.value).map_err(|e| anyhow!("配置解析失败: {}", e))?;
+        Ok(LayeredConfig {
+            config,
+            origins: layered.origins,
+        })
+    }
+}
+
+/// 合并后的配置及每个字段的来源
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    pub config: Config,
+    origins: BTreeMap<String, ConfigSource>,
+}
+
+impl LayeredConfig {
+    /// 字段的来源，`path`如`client.name`
+    pub fn origin(&self, path: &str) -> Option<&ConfigSource> {
+        self.origins.get(path)
+    }
+
+    /// 配置检查错误的来源：字段本身或所在的列表、映射，否则为其中第一个有来源的字段
+    pub fn source_of(&self, path: &str) -> Option<&ConfigSource> {
+        let mut prefix = path;
+        loop {
+            if let Some(source) = self.origins.get(prefix) {
+                return Some(source);
+            }
+            match prefix.rfind(['.', '[']) {
+                Some(i) => prefix = &prefix[..i],
+                None => break,
+            }
+        }
+        self.origins
+            .iter()
+            .find(|(key, _)| {
+                key.strip_prefix(path)
+                    .is_some_and(|rest| rest.starts_with('.'))
+            })
+            .map(|(_, source)| source)
+    }
+
+    /// 列出配置检查的错误，每行后注明出错字段的来源
+    pub fn describe_errors(&self, errors: &ConfigErrors) -> String {
+        let mut text = format!("配置有{}处错误:", errors.0.len());
+        for error in &errors.0 {
+            match self.source_of(&error.path) {
+                Some(source) => text += &format!("\n  {}  # {}", error, source),
+                None => text += &format!("\n  {}", error),
+            }
+        }
+        text
+    }
+
+    /// 按字段列出生效的配置和来源，每行`path: value  # source`，未配置而使用默认值的字段来源为`default`
+    pub fn describe(&self) -> String {
+        let value = serde_yaml::to_value(&self.config).unwrap_or(Value::Null);
+        let mut lines = vec![];
+        describe(&value, &mut vec![], &self.origins, &mut lines);
+        lines.join("\n")
+    }
+}
+
+/// 合并中的配置
+#[derive(Default)]
+struct Layers {
+    value: Value,
+    origins: BTreeMap<String, ConfigSource>,
+}
+
+impl Layers {
+    /// 把`value`合并到`path`处，映射逐层合并，其余值整体替换
+    fn merge(&mut self, path: &[String], value: Value, source: &ConfigSource) {
+        match (node_mut(&mut self.value, path), value) {
+            (Some(Value::Mapping(_)), Value::Mapping(mapping)) => {
+                for (key, value) in mapping {
+                    let mut path = path.to_vec();
+                    path.push(key_name(&key));
+                    self.merge(&path, value, source);
+                }
+            }
+            (_, value) => self.replace(path, value, source),
+        }
+    }
+
+    /// 设置单个字段
+    fn set(&mut self, path: &[&str], value: Value, source: &ConfigSource) {
+        let path: Vec<String> = path.iter().map(|p| p.to_string()).collect();
+        self.replace(&path, value, source);
+    }
+
+    /// 替换`path`处的值，缺少的上级映射自动创建
+    fn replace(&mut self, path: &[String], value: Value, source: &ConfigSource) {
+        let joined = path.join(".");
+        self.origins
+            .retain(|key, _| !(*key == joined || key.starts_with(&format!("{}.", joined))));
+        let mut leaves = vec![];
+        leaf_paths(&value, &mut path.to_vec(), &mut leaves);
+        for leaf in leaves {
+            self.origins.insert(leaf, source.clone());
+        }
+
+        let mut node = &mut self.value;
+        for key in path {
+            if !node.is_mapping() {
+                *node = Value::Mapping(Mapping::new());
+            }
+            let Value::Mapping(mapping) = node else {
+                unreachable!()
+            };
+            node = mapping
+                .entry(Value::String(key.clone()))
+                .or_insert(Value::Null);
+        }
+        *node = value;
+    }
+}
+
+/// `path`处的值
+fn node_mut<'a>(value: &'a mut Value, path: &[String]) -> Option<&'a mut Value> {
+    let mut node = value;
+    for key in path {
+        node = node.get_mut(key.as_str())?;
+    }
+    Some(node)
+}
+
+/// 映射的键转为字段路径的一段
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        key => serde_yaml::to_string(key)
+            .map(|s| s.trim_start_matches("---").trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// 值中所有叶子字段的路径，非空映射展开，其余值（包括列表）为叶子
+fn leaf_paths(value: &Value, path: &mut Vec<String>, leaves: &mut Vec<String>) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            for (key, value) in mapping {
+                path.push(key_name(key));
+                leaf_paths(value, path, leaves);
+                path.pop();
+            }
+        }
+        _ => leaves.push(path.join(".")),
+    }
+}
+
+fn describe(
+    value: &Value,
+    path: &mut Vec<String>,
+    origins: &BTreeMap<String, ConfigSource>,
+    lines: &mut Vec<String>,
+) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            for (key, value) in mapping {
+                path.push(key_name(key));
+                describe(value, path, origins, lines);
+                path.pop();
+            }
+        }
+        value => {
+            let joined = path.join(".");
+            let text = serde_json::to_string(value).unwrap_or_default();
+            match origins.get(&joined) {
+                Some(source) => lines.push(format!("{}: {}  # {}", joined, text, source)),
+                None => lines.push(format!("{}: {}  # default", joined, text)),
+            }
+        }
+    }
+}
+
+/// 环境变量对应的字段路径和是否按字符串处理
+fn env_key(name: &str) -> Option<(&'static str, bool)> {
+    let key = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+    ENV_KEYS
+        .iter()
+        .find(|(path, _)| path.replace('.', "_") == key)
+        .copied()
+}
+
+fn var<'a>(vars: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    vars.iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+        .filter(|value| !value.is_empty())
+}
+
+/// 系统配置文件路径
+fn system_path(vars: &[(String, String)]) -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        var(vars, "ProgramData").map(|dir| Path::new(dir).join(APP_DIR).join(LOCAL_CONFIG))
+    } else {
+        Some(Path::new("/etc").join(APP_DIR).join(LOCAL_CONFIG))
+    }
+}
+
+/// 用户配置目录，也用于保存服务器标识等本机数据
+pub fn user_dir() -> Option<PathBuf> {
+    app_dir(&env::vars().collect::<Vec<_>>())
+}
+
+/// 用户配置文件路径
+fn user_path(vars: &[(String, String)]) -> Option<PathBuf> {
+    Some(app_dir(vars)?.join(LOCAL_CONFIG))
+}
+
+fn app_dir(vars: &[(String, String)]) -> Option<PathBuf> {
+    let dir = if cfg!(target_os = "windows") {
+        PathBuf::from(var(vars, "APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        Path::new(var(vars, "HOME")?).join("Library/Application Support")
+    } else {
+        match var(vars, "XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(var(vars, "HOME")?).join(".config"),
+        }
+    };
+    Some(dir.join(APP_DIR))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConfigSource, ConfigSources};
+    use crate::{check_config, ConfigErrors};
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    fn temp_dir() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("minput-sources-{}", nanos));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = temp_dir();
+        let system = dir.join("system.yaml");
+        let user = dir.join("user.yaml");
+        fs::write(
+            &system,
+            "mode: client\nclient:\n  name: default\n  server_ip: 10.0.0.1\n  server_port: 48899\n  direction: left\n",
+        )
+        .unwrap();
+        fs::write(&user, "client:\n  server_ip: 10.0.0.2\n").unwrap();
+
+        let sources = ConfigSources::new()
+            .system(&system)
+            .user(&user)
+            .local(dir.join("missing.yaml"), false)
+            .env(vec![
+                ("MINPUT_CLIENT_NAME".to_string(), "office-7".to_string()),
+                ("MINPUT_CLIENT_SERVER_PORT".to_string(), "48900".to_string()),
+                ("PATH".to_string(), "/bin".to_string()),
+            ])
+            .arg("client.direction", "right");
+        let layered = sources.load().unwrap();
+        let client = layered.config.client.as_ref().unwrap();
+        assert_eq!(client.name, "office-7");
+        assert_eq!(client.server_ip.as_deref(), Some("10.0.0.2"));
+        assert_eq!(client.server_port, Some(48900));
+        assert_eq!(
+            layered.origin("client.server_ip"),
+            Some(&ConfigSource::User(user.clone()))
+        );
+        assert_eq!(
+            layered.origin("client.name"),
+            Some(&ConfigSource::Env("MINPUT_CLIENT_NAME".to_string()))
+        );
+        assert_eq!(
+            layered.origin("mode"),
+            Some(&ConfigSource::System(system.clone()))
+        );
+        assert_eq!(
+            layered.origin("client.direction"),
+            Some(&ConfigSource::Args)
+        );
+        let description = layered.describe();
+        assert!(description.contains("client.server_port: 48900  # env MINPUT_CLIENT_SERVER_PORT"));
+        assert!(description.contains("client.direction: \"right\"  # command line"));
+        //未配置的字段显示生效的默认值
+        assert!(
+            description.contains("client.discovery_port: 48898  # default"),
+            "{}",
+            description
+        );
+
+        //指定的本地文件必须存在
+        let sources = ConfigSources::new().local(dir.join("missing.yaml"), true);
+        assert!(sources.load().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_list_replaced() {
+        let dir = temp_dir();
+        let system = dir.join("system.yaml");
+        let local = dir.join("local.yaml");
+        fs::write(
+            &system,
+            "mode: server\nserver:\n  ip: 0.0.0.0\n  port: 48899\n  layout:\n    - {from: server, direction: left, to: a}\n    - {from: server, direction: right, to: b}\n",
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            "server:\n  layout:\n    - {from: server, direction: up, to: c}\n",
+        )
+        .unwrap();
+        let layered = ConfigSources::new()
+            .system(&system)
+            .local(&local, true)
+            .load()
+            .unwrap();
+        let server = layered.config.server.unwrap();
+        assert_eq!(server.layout.len(), 1);
+        assert_eq!(server.layout[0].to, "c");
+        assert_eq!(server.ip, vec!["0.0.0.0"]);
+
+        //重复的客户端在合并前报告
+        fs::write(&local, "server:\n  clients:\n    a: {}\n    a: {}\n").unwrap();
+        let error = ConfigSources::new()
+            .local(&local, true)
+            .load()
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("server.clients.a: 重复"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_error_source() {
+        let dir = temp_dir();
+        let system = dir.join("system.yaml");
+        fs::write(
+            &system,
+            "mode: server\nserver:\n  ip: 0.0.0.0\n  port: 48899\n  layout:\n    - {from: server, direction: left, to: a}\n",
+        )
+        .unwrap();
+        let layered = ConfigSources::new()
+            .system(&system)
+            .env(vec![("MINPUT_SERVER_PORT".to_string(), "0".to_string())])
+            .load()
+            .unwrap();
+        let env = ConfigSource::Env("MINPUT_SERVER_PORT".to_string());
+        assert_eq!(layered.source_of("server.port"), Some(&env));
+        assert_eq!(
+            layered.source_of("server.layout[0].to"),
+            Some(&ConfigSource::System(system.clone()))
+        );
+        assert_eq!(layered.source_of("server"), layered.origin("server.ip"));
+        assert_eq!(layered.source_of("client"), None);
+
+        let errors = check_config(&layered.config)
+            .unwrap_err()
+            .downcast::<ConfigErrors>()
+            .unwrap();
+        let description = layered.describe_errors(&errors);
+        assert!(
+            description.contains("server.port: 不能为0  # env MINPUT_SERVER_PORT"),
+            "{}",
+            description
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
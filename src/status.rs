@@ -0,0 +1,66 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+/// 服务端、客户端的状态变化，通过`Builder::on_status`注册的回调通知
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// 已启动，`addr`为本地绑定的地址
+    Started { addr: SocketAddr },
+    /// 服务端：客户端已连接
+    ClientConnected { name: String, addr: SocketAddr },
+    /// 服务端：客户端已断开（被踢出或超时未回复心跳）
+    ClientDisconnected { name: String, addr: SocketAddr },
+    /// 服务端：光标切换到`screen`屏幕
+    ScreenChanged { screen: String },
+    /// 客户端：收到服务端回复，连接成功
+    Connected { server: String },
+    /// 服务端：配置已重新加载，`restart_required`为需要重启才能生效的字段
+    Reloaded { restart_required: Vec<String> },
+    /// 服务端：新配置无效，继续使用原配置
+    ReloadFailed { error: String },
+    /// 已停止
+    Stopped,
+}
+
+pub type Callback = Arc<dyn Fn(&Status) + Send + Sync>;
+
+/// 状态回调，克隆后共享同一个回调，启动后设置的回调同样生效
+#[derive(Clone, Default)]
+pub struct Notifier {
+    callback: Arc<RwLock<Option<Callback>>>,
+}
+
+impl Notifier {
+    /// 设置回调，替换之前的回调
+    pub fn set<F>(&self, f: F)
+    where
+        F: Fn(&Status) + Send + Sync + 'static,
+    {
+        if let Ok(mut callback) = self.callback.write() {
+            *callback = Some(Arc::new(f));
+        }
+    }
+
+    /// 通知状态变化，回调在调用方线程中执行
+    pub fn notify(&self, status: Status) {
+        //先取出回调再调用，回调中可以重新设置回调
+        let callback = self.callback.read().ok().and_then(|c| c.clone());
+        if let Some(callback) = callback {
+            callback(&status);
+        }
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let set = self
+            .callback
+            .read()
+            .map(|c| c.is_some())
+            .unwrap_or_default();
+        f.debug_struct("Notifier").field("callback", &set).finish()
+    }
+}
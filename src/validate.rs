@@ -0,0 +1,456 @@
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_yaml::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    dev::keymap::Keymap, load_keyboard_layout, net::addr, Config, ConfigClient,
+    ConfigClientDirection, ConfigHotkeyAction, ConfigMode, ConfigServer, Display,
+};
+
+/// 配置问题，`path`为出错字段在YAML中的路径，如`server.layout[0].to`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// 配置检查发现的所有问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "配置有{}处错误:", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// 收集配置问题
+#[derive(Default)]
+struct Errors(Vec<ConfigError>);
+
+impl Errors {
+    fn push(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigError {
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+}
+
+/// 检查整个配置，返回发现的所有问题
+pub fn validate(config: &Config) -> Vec<ConfigError> {
+    let mut errors = Errors::default();
+    match config.mode {
+        ConfigMode::Server => match &config.server {
+            Some(server) => validate_server(server, &mut errors),
+            None => errors.push("server", "mode为server时需要server配置"),
+        },
+        ConfigMode::Client => match &config.client {
+            Some(client) => validate_client(client, &mut errors),
+            None => errors.push("client", "mode为client时需要client配置"),
+        },
+    }
+    if let Some(display) = &config.display {
+        validate_display(display, &mut errors);
+    }
+    if let Err(e) = load_keyboard_layout(config) {
+        errors.push("keyboard_layout", e.to_string());
+    }
+    if config.metrics_port == Some(0) {
+        errors.push("metrics_port", "不能为0");
+    }
+    errors.0
+}
+
+/// 查找YAML文本中重复的映射键，返回重复键的路径，如`server.clients.a`
+///
+/// 反序列化为映射时重复的键被后一个值覆盖，需要在解析文本时检查。YAML格式错误时返回已找到的部分。
+pub fn duplicate_keys(text: &str) -> Vec<String> {
+    let mut duplicates = vec![];
+    let seed = KeyPaths {
+        path: String::new(),
+        duplicates: &mut duplicates,
+    };
+    //格式错误在解析配置时报告
+    seed.deserialize(serde_yaml::Deserializer::from_str(text))
+        .ok();
+    duplicates
+}
+
+/// 检查YAML文本中重复的映射键，`source`为文本的来源，如配置文件路径
+pub fn check_duplicate_keys(text: &str, source: impl fmt::Display) -> Result<(), ConfigErrors> {
+    let errors: Vec<ConfigError> = duplicate_keys(text)
+        .into_iter()
+        .map(|path| ConfigError {
+            path,
+            message: format!("重复（{}）", source),
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigErrors(errors))
+    }
+}
+
+/// 遍历YAML值，记录重复键的路径
+struct KeyPaths<'a> {
+    path: String,
+    duplicates: &'a mut Vec<String>,
+}
+
+impl<'de> DeserializeSeed<'de> for KeyPaths<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeyPaths<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("YAML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let KeyPaths { path, duplicates } = self;
+        let mut i = 0;
+        while seq
+            .next_element_seed(KeyPaths {
+                path: format!("{}[{}]", path, i),
+                duplicates: &mut *duplicates,
+            })?
+            .is_some()
+        {
+            i += 1;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let KeyPaths { path, duplicates } = self;
+        let mut keys: HashSet<String> = HashSet::new();
+        while let Some(key) = map.next_key::<Value>()? {
+            let key = match key {
+                Value::String(key) => key,
+                key => serde_yaml::to_string(&key)
+                    .map(|s| s.trim_start_matches("---").trim().to_string())
+                    .unwrap_or_default(),
+            };
+            let child = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", path, key)
+            };
+            if !keys.insert(key) {
+                duplicates.push(child.clone());
+            }
+            map.next_value_seed(KeyPaths {
+                path: child,
+                duplicates: &mut *duplicates,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_server(server: &ConfigServer, errors: &mut Errors) {
+    if server.name.is_empty() {
+        errors.push("server.name", "不能为空");
+    }
+    if server.ip.is_empty() {
+        errors.push("server.ip", "至少需要一个监听地址");
+    }
+    for (i, ip) in server.ip.iter().enumerate() {
+        if let Err(e) = addr::check(ip) {
+            errors.push(format!("server.ip[{}]", i), e.to_string());
+        }
+    }
+    if server.port == 0 {
+        errors.push("server.port", "不能为0");
+    }
+    if server.discovery && server.discovery_port == 0 {
+        errors.push("server.discovery_port", "不能为0");
+    }
+    if cfg!(not(unix)) && server.control_socket.is_some() {
+        errors.push("server.control_socket", "当前系统不支持Unix域套接字");
+    }
+
+    let mut names: Vec<&String> = server.clients.keys().collect();
+    names.sort();
+    for name in names {
+        let path = format!("server.clients.{}", name);
+        if *name == server.name {
+            errors.push(&path, "与服务器屏幕名字相同");
+        }
+        if let Err(e) = Keymap::new(&server.clients[name].keymap) {
+            //Keymap的错误以keymap[i]开头
+            let e = e.to_string();
+            match e.split_once(": ") {
+                Some((field, message)) => errors.push(format!("{}.{}", path, field), message),
+                None => errors.push(&path, e),
+            }
+        }
+    }
+
+    validate_layout(server, errors);
+
+    for (i, hotkey) in server.hotkeys.iter().enumerate() {
+        if hotkey.keys.is_empty() {
+            errors.push(format!("server.hotkeys[{}].keys", i), "不能为空");
+        }
+        if let ConfigHotkeyAction::Switch { screen } = &hotkey.action {
+            let path = format!("server.hotkeys[{}].screen", i);
+            if screen.is_empty() {
+                errors.push(path, "不能为空");
+            } else if !is_screen(server, screen) {
+                errors.push(
+                    path,
+                    format!("屏幕{}不是服务器屏幕，也不在layout或clients中", screen),
+                );
+            }
+        }
+    }
+}
+
+/// 是否为服务器屏幕、布局中的屏幕或配置的客户端
+fn is_screen(server: &ConfigServer, screen: &str) -> bool {
+    screen == server.name
+        || server.clients.contains_key(screen)
+        || server
+            .layout
+            .iter()
+            .any(|link| link.from == screen || link.to == screen)
+}
+
+/// 检查屏幕布局：连接的屏幕、边缘范围、重复的连接以及与服务器屏幕的连通性
+fn validate_layout(server: &ConfigServer, errors: &mut Errors) {
+    //每条连接同时生成反向连接，同一屏幕同一方向只能有一个相邻屏幕
+    let mut edges: HashMap<(&str, ConfigClientDirection), usize> = HashMap::new();
+    let mut neighbors: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (i, link) in server.layout.iter().enumerate() {
+        let path = format!("server.layout[{}]", i);
+        if link.from.is_empty() {
+            errors.push(format!("{}.from", path), "不能为空");
+        }
+        if link.to.is_empty() {
+            errors.push(format!("{}.to", path), "不能为空");
+        }
+        if link.from == link.to {
+            errors.push(format!("{}.to", path), "不能连接到自身");
+            continue;
+        }
+        if !(0.0..100.0).contains(&link.start) {
+            errors.push(format!("{}.start", path), "需要在0~100之间");
+        }
+        if link.end <= link.start || link.end > 100.0 {
+            errors.push(format!("{}.end", path), "需要大于start且不超过100");
+        }
+        for edge in [
+            (link.from.as_str(), link.direction),
+            (link.to.as_str(), link.direction.opposite()),
+        ] {
+            if let Some(j) = edges.insert(edge, i) {
+                if j != i {
+                    errors.push(
+                        &path,
+                        format!("屏幕{}的{:?}方向与layout[{}]重复", edge.0, edge.1, j),
+                    );
+                }
+            }
+        }
+        neighbors.entry(&link.from).or_default().push(&link.to);
+        neighbors.entry(&link.to).or_default().push(&link.from);
+    }
+
+    //布局中的屏幕都需要经过连接到达服务器屏幕
+    let mut reached = HashSet::from([server.name.as_str()]);
+    let mut queue = vec![server.name.as_str()];
+    while let Some(screen) = queue.pop() {
+        for next in neighbors.get(screen).into_iter().flatten() {
+            if reached.insert(next) {
+                queue.push(next);
+            }
+        }
+    }
+    for (i, link) in server.layout.iter().enumerate() {
+        if link.from != link.to && !reached.contains(link.from.as_str()) {
+            errors.push(
+                format!("server.layout[{}]", i),
+                format!("屏幕{}未与服务器屏幕{}相连", link.from, server.name),
+            );
+        }
+    }
+}
+
+fn validate_client(client: &ConfigClient, errors: &mut Errors) {
+    if client.name.is_empty() {
+        errors.push("client.name", "不能为空");
+    }
+    match (&client.server_ip, client.server_port) {
+        (Some(ip), port) => {
+            if let Err(e) = addr::check(ip) {
+                errors.push("client.server_ip", e.to_string());
+            }
+            match port {
+                Some(0) => errors.push("client.server_port", "不能为0"),
+                None => errors.push("client.server_port", "配置server_ip时需要server_port"),
+                Some(_) => {}
+            }
+        }
+        (None, _) if client.discovery_port == 0 => errors.push(
+            "client.discovery_port",
+            "未配置server_ip时用于发现服务器，不能为0",
+        ),
+        (None, _) => {}
+    }
+}
+
+fn validate_display(display: &Display, errors: &mut Errors) {
+    if display.monitors.is_empty() {
+        errors.push("display.monitors", "至少需要一个显示器");
+    }
+    for (i, monitor) in display.monitors.iter().enumerate() {
+        if monitor.width == 0 || monitor.height == 0 {
+            errors.push(format!("display.monitors[{}]", i), "分辨率不能为0");
+        }
+        if monitor.scale <= 0.0 {
+            errors.push(format!("display.monitors[{}].scale", i), "需要大于0");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{duplicate_keys, validate};
+    use crate::Config;
+
+    #[test]
+    fn test_validate_reports_all() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+mode: server
+server:
+  name: pc
+  ip: 0.0.0.256
+  port: 0
+  clients:
+    pc: {}
+  layout:
+    - {from: pc, direction: right, to: laptop}
+    - {from: pc, direction: right, to: tablet, start: 50, end: 20}
+    - {from: phone, direction: up, to: phone}
+    - {from: a, direction: left, to: b}
+keyboard_layout: nowhere
+"#,
+        )
+        .unwrap();
+        let paths: Vec<_> = validate(&config).into_iter().map(|e| e.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "server.ip[0]",
+                "server.port",
+                "server.clients.pc",
+                "server.layout[1].end",
+                "server.layout[1]",
+                "server.layout[2].to",
+                "server.layout[3]",
+                "keyboard_layout",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_client() {
+        let config: Config = serde_yaml::from_str(
+            "mode: client\nclient:\n  name: ''\n  server_ip: 127.0.0.1\n  server_port: 48899\n  direction: left\n",
+        )
+        .unwrap();
+        let errors = validate(&config);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "client.name: 不能为空");
+
+        let error = serde_yaml::from_str::<Config>("mode: both\n").unwrap_err();
+        assert!(error.to_string().contains("mode"));
+    }
+
+    #[test]
+    fn test_hotkey_screen() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+mode: server
+server:
+  name: pc
+  ip: 127.0.0.1
+  port: 48899
+  clients:
+    laptop: {}
+  layout:
+    - {from: pc, direction: right, to: tablet}
+  hotkeys:
+    - {keys: [ControlLeft, Num1], action: switch, screen: laptop}
+    - {keys: [ControlLeft, Num2], action: switch, screen: tablet}
+    - {keys: [ControlLeft, Num3], action: switch, screen: pc}
+    - {keys: [ControlLeft, Num4], action: switch, screen: nosuch}
+"#,
+        )
+        .unwrap();
+        let errors = validate(&config);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "server.hotkeys[3].screen");
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let text = "server:\n  clients:\n    a: {}\n    b: {}\n    a: {}\n  layout:\n    - {from: a, from: b}\n";
+        assert_eq!(
+            duplicate_keys(text),
+            vec!["server.clients.a", "server.layout[0].from"]
+        );
+        assert!(duplicate_keys("mode: server\nserver: {name: a}\n").is_empty());
+        assert!(duplicate_keys("").is_empty());
+        assert!(duplicate_keys("mode: [").is_empty());
+    }
+}